@@ -2,9 +2,27 @@ use criterion::{Criterion, black_box, criterion_group, criterion_main};
 
 use deep_sea::{
     deep_sea::DiveDirection,
-    deep_sea_vectorization::{DeepSeaAction, DeepSeaState, DeepSeaStateActionPair},
-    ml::vectorization::*,
+    deep_sea_vectorization::{
+        DeepSeaAction, DeepSeaState, DeepSeaStateActionPair, EncoderConfig, Perspective,
+    },
+    ml::{batched_env::BatchedDeepSeaEnv, env::DeepSeaEnv, vectorization::*},
 };
+use rand::{SeedableRng, rngs::StdRng, seq::IndexedRandom};
+
+/// Every state of a few six-player games between random players, as models observe them.
+fn game_states() -> Vec<DeepSeaState> {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut states = vec![];
+    for _ in 0..10 {
+        let mut env = DeepSeaEnv::new(6).unwrap();
+        while env.pending().is_some() {
+            states.push(Perspective::acting(env.state()).observe(env.state()));
+            let action = *env.legal_actions().choose(&mut rng).unwrap();
+            env.step(action).unwrap();
+        }
+    }
+    states
+}
 
 fn bench_state_action_into_tensordata(c: &mut Criterion) {
     let default_state = black_box(DeepSeaState::default());
@@ -49,8 +67,66 @@ fn bench_state_action_unpacking(c: &mut Criterion) {
     });
 }
 
+fn bench_state_action_batch_into_tensordata(c: &mut Criterion) {
+    let default_state = black_box(DeepSeaState::default());
+    let action = DeepSeaAction::DiveDirection(DiveDirection::Down);
+    let state_actions: Vec<_> = (0..256)
+        .map(|_| DeepSeaStateActionPair {
+            state: &default_state,
+            action: &action,
+        })
+        .collect();
+    c.bench_function("256 starting states into batched tensordata", |b| {
+        b.iter(|| black_box(&state_actions).to_batch_tensordata::<f32>())
+    });
+}
+
+fn bench_game_states_unpacking(c: &mut Criterion) {
+    let states = black_box(game_states());
+    let config = EncoderConfig::padded();
+    let width = DeepSeaState::default().unpacked_size_with(config);
+    c.bench_function("game states collected from iterators", |b| {
+        b.iter(|| {
+            states
+                .iter()
+                .map(|state| state.unpack_with::<f32>(config).collect::<Vec<_>>())
+                .collect::<Vec<_>>()
+        })
+    });
+    let mut buffer = vec![0f32; states.len() * width];
+    c.bench_function("game states unpacked into one buffer", |b| {
+        b.iter(|| {
+            for (state, row) in states.iter().zip(buffer.chunks_exact_mut(width)) {
+                state.unpack_into(config, row);
+            }
+            black_box(&buffer);
+        })
+    });
+    c.bench_function("game states extended into one buffer", |b| {
+        b.iter(|| {
+            buffer.clear();
+            for state in &states {
+                buffer.extend(state.unpack_with::<f32>(config));
+            }
+            black_box(&buffer);
+        })
+    });
+}
+
+fn bench_batched_env_observations(c: &mut Criterion) {
+    let env = BatchedDeepSeaEnv::new(1024, 6, 0).unwrap();
+    let config = EncoderConfig::padded();
+    let mut buffer = vec![0f32; env.num_envs() * env.observation_size(config)];
+    c.bench_function("1024 batched games into observations", |b| {
+        b.iter(|| black_box(&env).observations_into(config, &mut buffer))
+    });
+}
+
 criterion_group!(
     benches,
+    bench_batched_env_observations,
+    bench_game_states_unpacking,
+    bench_state_action_batch_into_tensordata,
     bench_state_action_into_tensordata,
     bench_state_action_unpacking,
     bench_state_action_vectorization
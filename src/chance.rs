@@ -0,0 +1,197 @@
+use std::collections::VecDeque;
+
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+use crate::{engine::Engine, treasure::Treasure};
+
+/// The source of every chance outcome in a game: dice rolls for moves and the values of the
+/// treasure chips revealed when scoring.
+pub trait ChanceProvider {
+    /// The sum of two three-sided dice.
+    fn roll_dice(&mut self) -> u32;
+
+    /// Which value a chip of `treasure` turns out to have, as an offset from the treasure's
+    /// lowest value. `remaining[offset]` counts the chips of each value not yet revealed; the
+    /// chosen offset must have one left.
+    fn chip_value(&mut self, treasure: Treasure, remaining: &[u32; 4]) -> usize;
+}
+
+impl<C: ChanceProvider + ?Sized> ChanceProvider for &mut C {
+    fn roll_dice(&mut self) -> u32 {
+        (**self).roll_dice()
+    }
+
+    fn chip_value(&mut self, treasure: Treasure, remaining: &[u32; 4]) -> usize {
+        (**self).chip_value(treasure, remaining)
+    }
+}
+
+/// Draws outcomes at random, the way the physical game does.
+#[derive(Clone, Debug)]
+pub struct RngChance<R = StdRng> {
+    rng: R,
+}
+
+impl<R: Rng> RngChance<R> {
+    pub fn new(rng: R) -> Self {
+        Self { rng }
+    }
+}
+
+impl RngChance {
+    pub fn seeded(seed: u64) -> Self {
+        Self::new(StdRng::seed_from_u64(seed))
+    }
+}
+
+impl Default for RngChance {
+    fn default() -> Self {
+        Self::new(StdRng::from_rng(&mut rand::rng()))
+    }
+}
+
+impl<R: Rng> ChanceProvider for RngChance<R> {
+    fn roll_dice(&mut self) -> u32 {
+        Engine::roll_dice_with(&mut self.rng)
+    }
+
+    fn chip_value(&mut self, _treasure: Treasure, remaining: &[u32; 4]) -> usize {
+        let total: u32 = remaining.iter().sum();
+        let mut choice = self.rng.random_range(1..=total);
+        remaining
+            .iter()
+            .position(|&count| {
+                choice = choice.saturating_sub(count);
+                choice == 0
+            })
+            .unwrap()
+    }
+}
+
+/// Every outcome a provider produced, in order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChanceLog {
+    pub dice_rolls: Vec<u32>,
+    pub chip_values: Vec<usize>,
+}
+
+/// Plays back fixed outcomes, such as a `ChanceLog`. Panics once it runs out.
+#[derive(Clone, Debug, Default)]
+pub struct ScriptedChance {
+    dice_rolls: VecDeque<u32>,
+    chip_values: VecDeque<usize>,
+}
+
+impl ScriptedChance {
+    pub fn new(
+        dice_rolls: impl IntoIterator<Item = u32>,
+        chip_values: impl IntoIterator<Item = usize>,
+    ) -> Self {
+        Self {
+            dice_rolls: dice_rolls.into_iter().collect(),
+            chip_values: chip_values.into_iter().collect(),
+        }
+    }
+}
+
+impl From<ChanceLog> for ScriptedChance {
+    fn from(log: ChanceLog) -> Self {
+        Self::new(log.dice_rolls, log.chip_values)
+    }
+}
+
+impl ChanceProvider for ScriptedChance {
+    fn roll_dice(&mut self) -> u32 {
+        self.dice_rolls
+            .pop_front()
+            .expect("Chance script ran out of dice rolls")
+    }
+
+    fn chip_value(&mut self, _treasure: Treasure, _remaining: &[u32; 4]) -> usize {
+        self.chip_values
+            .pop_front()
+            .expect("Chance script ran out of chip values")
+    }
+}
+
+/// Passes outcomes through from another provider while logging them for replay.
+#[derive(Clone, Debug, Default)]
+pub struct RecordingChance<C> {
+    inner: C,
+    log: ChanceLog,
+}
+
+impl<C: ChanceProvider> RecordingChance<C> {
+    pub fn new(inner: C) -> Self {
+        Self {
+            inner,
+            log: ChanceLog::default(),
+        }
+    }
+
+    pub fn log(&self) -> &ChanceLog {
+        &self.log
+    }
+
+    pub fn into_log(self) -> ChanceLog {
+        self.log
+    }
+}
+
+impl<C: ChanceProvider> ChanceProvider for RecordingChance<C> {
+    fn roll_dice(&mut self) -> u32 {
+        let dice_roll = self.inner.roll_dice();
+        self.log.dice_rolls.push(dice_roll);
+        dice_roll
+    }
+
+    fn chip_value(&mut self, treasure: Treasure, remaining: &[u32; 4]) -> usize {
+        let chip_value = self.inner.chip_value(treasure, remaining);
+        self.log.chip_values.push(chip_value);
+        chip_value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        chance::{ChanceProvider, RecordingChance, RngChance, ScriptedChance},
+        treasure::Treasure,
+    };
+
+    #[test]
+    fn test_rng_chance_outcomes() {
+        let mut chance = RngChance::seeded(0);
+        for _ in 0..100 {
+            assert!((2..=6).contains(&chance.roll_dice()));
+            assert_eq!(chance.chip_value(Treasure::One, &[0, 0, 1, 0]), 2);
+        }
+    }
+
+    #[test]
+    fn test_recording_replays() {
+        let mut recording = RecordingChance::new(RngChance::seeded(3));
+        let outcomes: Vec<_> = (0..20)
+            .map(|_| {
+                (
+                    recording.roll_dice(),
+                    recording.chip_value(Treasure::Four, &[2, 2, 2, 2]),
+                )
+            })
+            .collect();
+
+        let mut replay = ScriptedChance::from(recording.into_log());
+        for (dice_roll, chip_value) in outcomes {
+            assert_eq!(replay.roll_dice(), dice_roll);
+            assert_eq!(replay.chip_value(Treasure::Four, &[2, 2, 2, 2]), chip_value);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "ran out of dice rolls")]
+    fn test_script_runs_out() {
+        let mut chance = ScriptedChance::new([4], []);
+        chance.roll_dice();
+        chance.roll_dice();
+    }
+}
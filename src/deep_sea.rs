@@ -12,6 +12,13 @@ use crate::{
     treasure::Treasure,
 };
 
+pub mod compact;
+pub mod moves;
+pub mod zobrist;
+
+/// Most players the game is played with.
+pub const MAX_PLAYERS: usize = 6;
+
 #[derive(Clone, Copy, Debug, EnumCount, PartialEq, Eq, Hash)]
 pub enum Tile {
     Empty,
@@ -50,29 +57,119 @@ impl Position {
         }
     }
 
-    pub fn advance(&self, direction: DiveDirection) -> DeepSeaResult<Position> {
+    /// The next tile in `direction`, or `None` for moves no diver can make: up before leaving
+    /// the submarine, or any move after returning to it.
+    pub fn advance(&self, direction: DiveDirection) -> Option<Position> {
         match (self, direction) {
-            (Self::Diving(0), DiveDirection::Up) => Ok(Self::ReturnedToSubmarine),
-            (Self::Diving(index), DiveDirection::Up) => Ok(Self::Diving(index - 1)),
-            (Self::Diving(index), DiveDirection::Down) => Ok(Self::Diving(index + 1)),
-            (Self::WaitingToDive, DiveDirection::Down) => Ok(Self::Diving(0)),
-            (Self::WaitingToDive, DiveDirection::Up) => Err(DeepSeaError::Internal(
-                "Cannot move up before leaving submarine".to_owned(),
-            )
-            .into()),
-            (Self::ReturnedToSubmarine, _) => Err(DeepSeaError::Internal(
-                "Cannot move after returned to submarine".to_owned(),
-            )
-            .into()),
+            (Self::Diving(0), DiveDirection::Up) => Some(Self::ReturnedToSubmarine),
+            (Self::Diving(index), DiveDirection::Up) => Some(Self::Diving(index - 1)),
+            (Self::Diving(index), DiveDirection::Down) => Some(Self::Diving(index + 1)),
+            (Self::WaitingToDive, DiveDirection::Down) => Some(Self::Diving(0)),
+            (Self::WaitingToDive, DiveDirection::Up) | (Self::ReturnedToSubmarine, _) => None,
+        }
+    }
+
+    /// Directions a diver here may choose on a path of `path_len` tiles. Diving on from the last
+    /// tile is excluded, as a player holding no treasure could otherwise stall the game forever.
+    pub fn legal_directions(&self, path_len: usize) -> &'static [DiveDirection] {
+        match self {
+            Self::Diving(index) if *index == path_len - 1 => &[DiveDirection::Up],
+            Self::Diving(_) => &[DiveDirection::Down, DiveDirection::Up],
+            Self::WaitingToDive | Self::ReturnedToSubmarine => &[DiveDirection::Down],
         }
     }
 }
 
-#[derive(Clone, Debug)]
+/// One turn a player took, as every player at the table saw it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Turn {
+    pub direction: DiveDirection,
+    /// `None` if the player did not end the turn on a tile.
+    pub treasure: Option<TreasureDecision>,
+}
+
+/// Most turns a `TurnHistory` keeps one by one. Older turns only count towards its totals.
+pub const MAX_RECENT_TURNS: usize = 8;
+
+/// The turns a player has taken: the latest `MAX_RECENT_TURNS` of them, and totals over the whole
+/// game. Its size is fixed, so a game does not get more expensive to copy as it goes on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TurnHistory {
+    /// A ring of the latest turns, the next one going into slot `num_turns % MAX_RECENT_TURNS`.
+    recent: [Option<Turn>; MAX_RECENT_TURNS],
+    num_turns: usize,
+    turned_around: Option<usize>,
+    treasures_taken: usize,
+    treasures_returned: usize,
+}
+
+impl TurnHistory {
+    fn push(&mut self, direction: DiveDirection) {
+        if direction == DiveDirection::Up && self.turned_around.is_none() {
+            self.turned_around = Some(self.num_turns);
+        }
+        self.recent[self.num_turns % MAX_RECENT_TURNS] = Some(Turn {
+            direction,
+            treasure: None,
+        });
+        self.num_turns += 1;
+    }
+
+    /// Records the treasure decision made at the end of the latest turn.
+    fn decide_treasure(&mut self, decision: TreasureDecision) {
+        let Some(latest) = self.num_turns.checked_sub(1) else {
+            return;
+        };
+        let Some(turn) = &mut self.recent[latest % MAX_RECENT_TURNS] else {
+            return;
+        };
+        if let Some(count) = turn.treasure.replace(decision).and_then(|p| self.tally(p)) {
+            *count -= 1;
+        }
+        if let Some(count) = self.tally(decision) {
+            *count += 1;
+        }
+    }
+
+    fn tally(&mut self, decision: TreasureDecision) -> Option<&mut usize> {
+        match decision {
+            TreasureDecision::Take => Some(&mut self.treasures_taken),
+            TreasureDecision::Return(_) => Some(&mut self.treasures_returned),
+            TreasureDecision::Ignore => None,
+        }
+    }
+
+    /// Every turn taken so far, including those no longer kept.
+    pub fn num_turns(&self) -> usize {
+        self.num_turns
+    }
+
+    /// The kept turns, latest first.
+    pub fn recent(&self) -> impl Iterator<Item = Turn> + '_ {
+        (0..self.num_turns.min(MAX_RECENT_TURNS))
+            .filter_map(|age| self.recent[(self.num_turns - 1 - age) % MAX_RECENT_TURNS])
+    }
+
+    /// Turns taken before the first one heading up, if the player has turned around.
+    pub fn turned_around(&self) -> Option<usize> {
+        self.turned_around
+    }
+
+    pub fn treasures_taken(&self) -> usize {
+        self.treasures_taken
+    }
+
+    pub fn treasures_returned(&self) -> usize {
+        self.treasures_returned
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Player {
     direction: DiveDirection,
     position: Position,
     held_treasures: Vec<Treasure>,
+    turns: TurnHistory,
 }
 
 impl Player {
@@ -81,6 +178,7 @@ impl Player {
             direction: DiveDirection::Down,
             position: Position::WaitingToDive,
             held_treasures: vec![],
+            turns: TurnHistory::default(),
         }
     }
 
@@ -95,15 +193,21 @@ impl Player {
     pub fn held_treasures(&self) -> &[Treasure] {
         &self.held_treasures
     }
+
+    pub fn turns(&self) -> &TurnHistory {
+        &self.turns
+    }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct DeepSea {
     path: Vec<Tile>,
     players: Vec<Player>,
     player_idx: usize,
     oxygen: u32,
     occupied_tiles: BitSet,
+    /// Kept up to date by every move, see `zobrist_hash`.
+    zobrist_hash: u64,
 }
 
 impl DeepSea {
@@ -111,13 +215,43 @@ impl DeepSea {
 
     pub fn new(path: Vec<Tile>, num_players: usize) -> Self {
         let path_len = path.len();
-        Self {
+        let mut deep_sea = Self {
             path,
             players: (0..num_players).map(|_| Player::new()).collect(),
             player_idx: 0,
             oxygen: Self::OXYGEN,
             occupied_tiles: BitSet::with_capacity(path_len),
+            zobrist_hash: 0,
+        };
+        deep_sea.zobrist_hash = deep_sea.compute_zobrist_hash();
+        deep_sea
+    }
+
+    /// A game in progress, for mirrors of the rules that keep their own representation. The
+    /// players' turn history starts out empty.
+    pub(crate) fn from_parts(
+        path: Vec<Tile>,
+        players: impl IntoIterator<Item = (DiveDirection, Position, Vec<Treasure>)>,
+        player_idx: usize,
+        oxygen: u32,
+    ) -> Self {
+        let mut deep_sea = Self::new(path, 0);
+        deep_sea.players = players
+            .into_iter()
+            .map(|(direction, position, held_treasures)| Player {
+                direction,
+                position,
+                held_treasures,
+                turns: TurnHistory::default(),
+            })
+            .collect();
+        for idx in 0..deep_sea.players.len() {
+            deep_sea.enter_tile(deep_sea.players[idx].position);
         }
+        deep_sea.player_idx = player_idx;
+        deep_sea.oxygen = oxygen;
+        deep_sea.zobrist_hash = deep_sea.compute_zobrist_hash();
+        deep_sea
     }
 
     pub fn path(&self) -> &[Tile] {
@@ -140,6 +274,17 @@ impl DeepSea {
         &self.occupied_tiles
     }
 
+    /// A Zobrist hash of the position, equal for states with equal `canonical_key`s. Stable
+    /// across runs, so it may be stored alongside solver results.
+    pub fn zobrist_hash(&self) -> u64 {
+        self.zobrist_hash
+    }
+
+    /// The game packed for a rule error, if it fits.
+    pub(crate) fn snapshot(&self) -> Option<Box<compact::CompactDeepSea>> {
+        compact::CompactDeepSea::try_from(self).ok().map(Box::new)
+    }
+
     fn at_end(&self, position: Position) -> bool {
         position == Position::Diving(self.path.len() - 1)
     }
@@ -179,26 +324,44 @@ impl DeepSea {
 
     pub fn take_oxygen(&mut self) {
         let player = &self.players[self.player_idx];
-        self.oxygen = self
+        let oxygen = self
             .oxygen
             .saturating_sub(player.held_treasures.len() as u32);
+        self.zobrist_hash ^= zobrist::oxygen_key(self.oxygen) ^ zobrist::oxygen_key(oxygen);
+        self.oxygen = oxygen;
     }
 
     pub fn move_player(&mut self, direction: DiveDirection, mut dice_roll: u32) -> DeepSeaResult {
         let player = &self.players[self.player_idx];
+        match (player.position(), direction) {
+            (Position::ReturnedToSubmarine, _) => {
+                return Err(DeepSeaError::MoveAfterReturning {
+                    player_idx: self.player_idx,
+                    state: self.snapshot(),
+                });
+            }
+            (Position::WaitingToDive, DiveDirection::Up) => {
+                return Err(DeepSeaError::IllegalDirection {
+                    player_idx: self.player_idx,
+                    direction,
+                    position: player.position(),
+                    state: self.snapshot(),
+                });
+            }
+            _ => {}
+        }
         dice_roll = dice_roll.saturating_sub(player.held_treasures().len() as u32);
 
         let mut cur_player_pos = player.position();
         let mut player_pos = cur_player_pos;
-        while dice_roll > 0
-            && cur_player_pos != Position::ReturnedToSubmarine
-            && cur_player_pos != Position::Diving(self.path.len() - 1)
-        {
-            if direction == DiveDirection::Down && self.at_end(player_pos) {
+        while dice_roll > 0 && cur_player_pos != Position::ReturnedToSubmarine {
+            if direction == DiveDirection::Down && self.at_end(cur_player_pos) {
                 break;
             }
 
-            cur_player_pos = cur_player_pos.advance(direction)?;
+            cur_player_pos = cur_player_pos
+                .advance(direction)
+                .expect("Moves no diver can make are rejected above");
             if !self.occupied(cur_player_pos) {
                 dice_roll -= 1;
                 player_pos = cur_player_pos;
@@ -208,49 +371,77 @@ impl DeepSea {
         self.leave_tile(player.position());
         self.enter_tile(player_pos);
         let player = &mut self.players[self.player_idx];
+        self.zobrist_hash ^=
+            zobrist::player_key(self.player_idx, player.position, player.direction)
+                ^ zobrist::player_key(self.player_idx, player_pos, direction);
         player.direction = direction;
         player.position = player_pos;
+        player.turns.push(direction);
         Ok(())
     }
 
     pub fn take_treasure(&mut self, treasure: TreasureDecision) -> DeepSeaResult {
+        self.apply_treasure_decision(treasure)?;
+        self.players[self.player_idx]
+            .turns
+            .decide_treasure(treasure);
+        Ok(())
+    }
+
+    fn apply_treasure_decision(&mut self, treasure: TreasureDecision) -> DeepSeaResult {
         let player = &mut self.players[self.player_idx];
         let tile_idx = player.position().as_diving().unwrap();
         match treasure {
             TreasureDecision::Take => {
                 if let Tile::Treasure(treasure) = self.path[tile_idx] {
                     player.held_treasures.push(treasure);
+                    let copies = player
+                        .held_treasures
+                        .iter()
+                        .filter(|&&t| t == treasure)
+                        .count();
+                    self.zobrist_hash ^= zobrist::held_key(self.player_idx, treasure, copies)
+                        ^ zobrist::tile_key(tile_idx, self.path[tile_idx]);
                     self.path[tile_idx] = Tile::Empty;
                     Ok(())
                 } else {
-                    Err(DeepSeaError::AgentError(format!(
-                        "Cannot take treasure from {tile_idx}: {:?}",
-                        self.path[tile_idx]
-                    ))
-                    .into())
+                    Err(DeepSeaError::TakeFromEmptyTile {
+                        player_idx: self.player_idx,
+                        tile_idx,
+                        state: self.snapshot(),
+                    })
                 }
             }
             TreasureDecision::Return(treasure) => {
                 if self.path[tile_idx] != Tile::Empty {
-                    Err(DeepSeaError::AgentError(format!(
-                        "Cannot put treasure back in non-empty tile {tile_idx}: {:?}",
-                        self.path[tile_idx]
-                    ))
-                    .into())
+                    Err(DeepSeaError::ReturnOntoOccupiedTile {
+                        player_idx: self.player_idx,
+                        tile_idx,
+                        tile: self.path[tile_idx],
+                        state: self.snapshot(),
+                    })
                 } else if let Some((treasure_idx, &treasure)) = player
                     .held_treasures
                     .iter()
                     .find_position(|t| **t == treasure)
                 {
+                    let copies = player
+                        .held_treasures
+                        .iter()
+                        .filter(|&&t| t == treasure)
+                        .count();
                     player.held_treasures.remove(treasure_idx);
                     self.path[tile_idx] = Tile::Treasure(treasure);
+                    self.zobrist_hash ^= zobrist::held_key(self.player_idx, treasure, copies)
+                        ^ zobrist::tile_key(tile_idx, self.path[tile_idx]);
                     Ok(())
                 } else {
-                    Err(DeepSeaError::AgentError(format!(
-                        "Player is not holding treasure {:?}: {:?}",
-                        treasure, player.held_treasures
-                    ))
-                    .into())
+                    Err(DeepSeaError::TreasureNotHeld {
+                        player_idx: self.player_idx,
+                        treasure,
+                        held: player.held_treasures.clone(),
+                        state: self.snapshot(),
+                    })
                 }
             }
             TreasureDecision::Ignore => Ok(()),
@@ -258,7 +449,39 @@ impl DeepSea {
     }
 
     pub fn next_player(&mut self) {
-        self.player_idx = (self.player_idx + 1) % self.players.len();
+        let player_idx = (self.player_idx + 1) % self.players.len();
+        self.zobrist_hash ^= zobrist::turn_key(self.player_idx) ^ zobrist::turn_key(player_idx);
+        self.player_idx = player_idx;
+    }
+
+    /// Directions the current player may choose, see `Position::legal_directions`. Only
+    /// meaningful while the player is still diving down, which is the only time `Engine` asks for
+    /// a direction.
+    pub fn legal_directions(&self) -> Vec<DiveDirection> {
+        self.players[self.player_idx]
+            .position()
+            .legal_directions(self.path.len())
+            .to_vec()
+    }
+
+    /// Treasure decisions the current player may make on the tile they occupy.
+    pub fn legal_treasure_decisions(&self) -> Vec<TreasureDecision> {
+        let player = &self.players[self.player_idx];
+        let Some(tile_idx) = player.position().as_diving() else {
+            return vec![TreasureDecision::Ignore];
+        };
+        match self.path[tile_idx] {
+            Tile::Treasure(_) => vec![TreasureDecision::Ignore, TreasureDecision::Take],
+            Tile::Empty => std::iter::once(TreasureDecision::Ignore)
+                .chain(
+                    player
+                        .held_treasures
+                        .iter()
+                        .unique()
+                        .map(|&treasure| TreasureDecision::Return(treasure)),
+                )
+                .collect(),
+        }
     }
 }
 
@@ -319,12 +542,15 @@ impl Display for DeepSea {
 mod tests {
     use googletest::{
         expect_eq, expect_false, expect_that, expect_true, gtest,
-        prelude::{empty, ok, pat, unordered_elements_are},
+        prelude::{empty, pat, some, unordered_elements_are},
     };
 
     use crate::{
-        deep_sea::{DeepSea, DiveDirection, Player, Position, Tile, Treasure},
-        error::DeepSeaResult,
+        deep_sea::{
+            DeepSea, DiveDirection, MAX_RECENT_TURNS, Player, Position, Tile, Treasure, Turn,
+            compact::{CompactDeepSea, MAX_PATH_LENGTH},
+        },
+        error::{DeepSeaError, DeepSeaResult},
         solver::TreasureDecision,
     };
 
@@ -332,31 +558,31 @@ mod tests {
     fn test_advance_position() {
         expect_that!(
             Position::WaitingToDive.advance(DiveDirection::Down),
-            ok(pat!(Position::Diving(&0)))
+            some(pat!(Position::Diving(0)))
         );
-        expect_true!(Position::WaitingToDive.advance(DiveDirection::Up).is_err());
+        expect_true!(Position::WaitingToDive.advance(DiveDirection::Up).is_none());
         expect_true!(
             Position::ReturnedToSubmarine
                 .advance(DiveDirection::Down)
-                .is_err()
+                .is_none()
         );
         expect_true!(
             Position::ReturnedToSubmarine
                 .advance(DiveDirection::Up)
-                .is_err()
+                .is_none()
         );
 
         expect_that!(
             Position::Diving(10).advance(DiveDirection::Down),
-            ok(pat!(Position::Diving(&11)))
+            some(pat!(Position::Diving(11)))
         );
         expect_that!(
             Position::Diving(8).advance(DiveDirection::Up),
-            ok(pat!(Position::Diving(&7)))
+            some(pat!(Position::Diving(7)))
         );
         expect_that!(
             Position::Diving(0).advance(DiveDirection::Up),
-            ok(pat!(Position::ReturnedToSubmarine))
+            some(pat!(Position::ReturnedToSubmarine))
         );
     }
 
@@ -370,6 +596,7 @@ mod tests {
                 direction: pat!(DiveDirection::Down),
                 position: pat!(Position::WaitingToDive),
                 held_treasures: empty(),
+                ..
             })
         );
         expect_false!(deep_sea.occupied(Position::Diving(0)));
@@ -388,6 +615,7 @@ mod tests {
                 direction: pat!(DiveDirection::Down),
                 position: pat!(Position::Diving(&0)),
                 held_treasures: empty(),
+                ..
             })
         );
         expect_true!(deep_sea.occupied(Position::Diving(0)));
@@ -406,6 +634,7 @@ mod tests {
                 direction: pat!(DiveDirection::Down),
                 position: pat!(Position::Diving(&1)),
                 held_treasures: empty(),
+                ..
             })
         );
         expect_true!(deep_sea.occupied(Position::Diving(1)));
@@ -419,6 +648,7 @@ mod tests {
                 direction: pat!(DiveDirection::Down),
                 position: pat!(Position::Diving(&2)),
                 held_treasures: empty(),
+                ..
             })
         );
         expect_true!(deep_sea.occupied(Position::Diving(2)));
@@ -437,6 +667,7 @@ mod tests {
                 direction: pat!(DiveDirection::Down),
                 position: pat!(Position::Diving(&1)),
                 held_treasures: empty(),
+                ..
             })
         );
         expect_true!(deep_sea.occupied(Position::Diving(1)));
@@ -450,6 +681,7 @@ mod tests {
                 direction: pat!(DiveDirection::Down),
                 position: pat!(Position::Diving(&2)),
                 held_treasures: empty(),
+                ..
             })
         );
         expect_false!(deep_sea.occupied(Position::Diving(1)));
@@ -458,6 +690,29 @@ mod tests {
         Ok(())
     }
 
+    #[gtest]
+    fn test_turn_around_at_end() -> DeepSeaResult {
+        let mut deep_sea = DeepSea::new((0..3).map(|_| Tile::Empty).collect(), 2);
+
+        deep_sea.move_player(DiveDirection::Down, 2)?;
+        deep_sea.next_player();
+        deep_sea.move_player(DiveDirection::Down, 2)?;
+        expect_eq!(deep_sea.players[1].position, Position::Diving(2));
+
+        // Blocked at the bottom, so diving further stays put.
+        deep_sea.move_player(DiveDirection::Down, 2)?;
+        expect_eq!(deep_sea.players[1].position, Position::Diving(2));
+
+        deep_sea.move_player(DiveDirection::Up, 2)?;
+        expect_that!(
+            deep_sea.players[1].position,
+            pat!(Position::ReturnedToSubmarine)
+        );
+        expect_false!(deep_sea.occupied(Position::Diving(2)));
+
+        Ok(())
+    }
+
     #[gtest]
     fn test_take_treasure() -> DeepSeaResult {
         let mut deep_sea = DeepSea::new(vec![Tile::Treasure(Treasure::One)], 1);
@@ -469,6 +724,7 @@ mod tests {
                 direction: pat!(DiveDirection::Down),
                 position: pat!(Position::Diving(&0)),
                 held_treasures: empty(),
+                ..
             })
         );
         deep_sea.take_treasure(TreasureDecision::Ignore)?;
@@ -482,16 +738,163 @@ mod tests {
             unordered_elements_are![&Treasure::One]
         );
 
+        expect_true!(matches!(
+            deep_sea.take_treasure(TreasureDecision::Take),
+            Err(DeepSeaError::TakeFromEmptyTile {
+                player_idx: 0,
+                tile_idx: 0,
+                ..
+            })
+        ));
+
+        let err = deep_sea
+            .take_treasure(TreasureDecision::Return(Treasure::Four))
+            .unwrap_err();
+        expect_true!(matches!(
+            &err,
+            DeepSeaError::TreasureNotHeld {
+                treasure: Treasure::Four,
+                held,
+                ..
+            } if held == &[Treasure::One]
+        ));
+        expect_eq!(err.player_idx(), Some(0));
+        deep_sea.take_treasure(TreasureDecision::Return(Treasure::One))?;
+        expect_eq!(deep_sea.path[0], Tile::Treasure(Treasure::One));
+        expect_that!(deep_sea.players[0].held_treasures, empty());
+
+        Ok(())
+    }
+
+    #[gtest]
+    fn test_rule_errors() -> DeepSeaResult {
+        let mut deep_sea = DeepSea::new(vec![Tile::Treasure(Treasure::One), Tile::Empty], 1);
+        let before = CompactDeepSea::try_from(&deep_sea)?;
+        let err = deep_sea.move_player(DiveDirection::Up, 2).unwrap_err();
+        expect_true!(matches!(
+            err,
+            DeepSeaError::IllegalDirection {
+                player_idx: 0,
+                direction: DiveDirection::Up,
+                position: Position::WaitingToDive,
+                ..
+            }
+        ));
+        expect_eq!(err.state(), Some(&before));
+
+        deep_sea.move_player(DiveDirection::Down, 1)?;
+        let before = CompactDeepSea::try_from(&deep_sea)?;
+        let err = deep_sea
+            .take_treasure(TreasureDecision::Return(Treasure::One))
+            .unwrap_err();
+        expect_true!(matches!(
+            err,
+            DeepSeaError::ReturnOntoOccupiedTile {
+                player_idx: 0,
+                tile_idx: 0,
+                tile: Tile::Treasure(Treasure::One),
+                ..
+            }
+        ));
+        expect_eq!(err.state(), Some(&before));
+
+        deep_sea.move_player(DiveDirection::Up, 1)?;
+        let before = CompactDeepSea::try_from(&deep_sea)?;
+        let err = deep_sea.move_player(DiveDirection::Up, 1).unwrap_err();
+        expect_true!(matches!(
+            err,
+            DeepSeaError::MoveAfterReturning { player_idx: 0, .. }
+        ));
+        expect_eq!(err.state(), Some(&before));
+
+        // Games too large to pack carry no snapshot.
+        let mut deep_sea = DeepSea::new(vec![Tile::Empty; MAX_PATH_LENGTH + 1], 1);
+        let err = deep_sea.move_player(DiveDirection::Up, 2).unwrap_err();
+        expect_eq!(err.state(), None);
+
+        Ok(())
+    }
+
+    #[gtest]
+    fn test_turns() -> DeepSeaResult {
+        let mut deep_sea = DeepSea::new(vec![Tile::Treasure(Treasure::One), Tile::Empty], 1);
+        deep_sea.move_player(DiveDirection::Down, 1)?;
+        deep_sea.take_treasure(TreasureDecision::Take)?;
         expect_true!(deep_sea.take_treasure(TreasureDecision::Take).is_err());
+        deep_sea.move_player(DiveDirection::Up, 3)?;
+
+        let turns = deep_sea.players[0].turns();
+        expect_eq!(
+            turns.recent().collect::<Vec<_>>(),
+            [
+                Turn {
+                    direction: DiveDirection::Up,
+                    treasure: None,
+                },
+                Turn {
+                    direction: DiveDirection::Down,
+                    treasure: Some(TreasureDecision::Take),
+                },
+            ]
+        );
+        expect_eq!(turns.turned_around(), Some(1));
+        expect_eq!(turns.treasures_taken(), 1);
 
+        Ok(())
+    }
+
+    #[gtest]
+    fn test_turn_history_is_bounded() -> DeepSeaResult {
+        let mut deep_sea = DeepSea::new(vec![Tile::Empty; 50], 1);
+        for _ in 0..20 {
+            deep_sea.move_player(DiveDirection::Down, 1)?;
+            deep_sea.take_treasure(TreasureDecision::Ignore)?;
+        }
+
+        let turns = deep_sea.players[0].turns();
+        expect_eq!(turns.num_turns(), 20);
+        expect_eq!(turns.recent().count(), MAX_RECENT_TURNS);
         expect_true!(
-            deep_sea
-                .take_treasure(TreasureDecision::Return(Treasure::Four))
-                .is_err()
+            turns
+                .recent()
+                .all(|turn| turn.treasure == Some(TreasureDecision::Ignore))
+        );
+        expect_eq!(turns.turned_around(), None);
+
+        Ok(())
+    }
+
+    #[gtest]
+    fn test_legal_actions() -> DeepSeaResult {
+        let mut deep_sea = DeepSea::new(vec![Tile::Treasure(Treasure::Two), Tile::Empty], 1);
+        expect_that!(
+            deep_sea.legal_directions(),
+            unordered_elements_are![&DiveDirection::Down]
+        );
+
+        deep_sea.move_player(DiveDirection::Down, 1)?;
+        expect_that!(
+            deep_sea.legal_directions(),
+            unordered_elements_are![&DiveDirection::Down, &DiveDirection::Up]
+        );
+        expect_that!(
+            deep_sea.legal_treasure_decisions(),
+            unordered_elements_are![&TreasureDecision::Ignore, &TreasureDecision::Take]
+        );
+
+        deep_sea.take_treasure(TreasureDecision::Take)?;
+        deep_sea.move_player(DiveDirection::Down, 2)?;
+        expect_that!(
+            deep_sea.legal_directions(),
+            unordered_elements_are![&DiveDirection::Up]
+        );
+        expect_that!(
+            deep_sea.legal_treasure_decisions(),
+            unordered_elements_are![
+                &TreasureDecision::Ignore,
+                &TreasureDecision::Return(Treasure::Two)
+            ]
         );
-        deep_sea.take_treasure(TreasureDecision::Return(Treasure::One))?;
-        expect_eq!(deep_sea.path[0], Tile::Treasure(Treasure::One));
-        expect_that!(deep_sea.players[0].held_treasures, empty());
 
         Ok(())
     }
@@ -0,0 +1,520 @@
+use crate::{
+    deep_sea::{DeepSea, DiveDirection, MAX_PLAYERS, Position, Tile},
+    error::{DeepSeaError, DeepSeaResult},
+    solver::TreasureDecision,
+    treasure::Treasure,
+};
+
+/// Longest path a `CompactDeepSea` holds, one nibble per tile.
+pub const MAX_PATH_LENGTH: usize = 32;
+/// Most treasures one player can hold, two bits each.
+const MAX_HELD: usize = 32;
+
+const TREASURES: [Treasure; 4] = [
+    Treasure::One,
+    Treasure::Two,
+    Treasure::Three,
+    Treasure::Four,
+];
+const WAITING_TO_DIVE: u8 = u8::MAX - 1;
+const RETURNED_TO_SUBMARINE: u8 = u8::MAX;
+
+fn treasure_code(treasure: Treasure) -> u8 {
+    TREASURES.iter().position(|&t| t == treasure).unwrap() as u8
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct CompactPlayer {
+    direction: DiveDirection,
+    /// Depth while diving, otherwise `WAITING_TO_DIVE` or `RETURNED_TO_SUBMARINE`.
+    position: u8,
+    num_held: u8,
+    /// Two bits per held treasure, the first held in the lowest bits.
+    held: u64,
+}
+
+impl CompactPlayer {
+    const NEW: Self = Self {
+        direction: DiveDirection::Down,
+        position: WAITING_TO_DIVE,
+        num_held: 0,
+        held: 0,
+    };
+
+    fn position(&self) -> Position {
+        match self.position {
+            WAITING_TO_DIVE => Position::WaitingToDive,
+            RETURNED_TO_SUBMARINE => Position::ReturnedToSubmarine,
+            depth => Position::Diving(depth as usize),
+        }
+    }
+
+    fn set_position(&mut self, position: Position) {
+        self.position = match position {
+            Position::Diving(depth) => depth as u8,
+            Position::WaitingToDive => WAITING_TO_DIVE,
+            Position::ReturnedToSubmarine => RETURNED_TO_SUBMARINE,
+        };
+    }
+
+    fn held_treasures(&self) -> impl Iterator<Item = Treasure> + use<> {
+        let held = self.held;
+        (0..self.num_held as usize).map(move |idx| TREASURES[(held >> (2 * idx)) as usize & 0b11])
+    }
+
+    fn hold(&mut self, treasure: Treasure) -> DeepSeaResult {
+        if self.num_held as usize == MAX_HELD {
+            return Err(DeepSeaError::Internal(format!(
+                "Cannot hold more than {MAX_HELD} treasures"
+            )));
+        }
+        self.held |= (treasure_code(treasure) as u64) << (2 * self.num_held);
+        self.num_held += 1;
+        Ok(())
+    }
+
+    /// Drops the first held `treasure`, returning whether there was one.
+    fn drop_treasure(&mut self, treasure: Treasure) -> bool {
+        let Some(idx) = self.held_treasures().position(|t| t == treasure) else {
+            return false;
+        };
+        let below = self.held & ((1u64 << (2 * idx)) - 1);
+        let above = self.held.checked_shr(2 * idx as u32 + 2).unwrap_or(0);
+        self.held = below | (above << (2 * idx));
+        self.num_held -= 1;
+        true
+    }
+}
+
+/// Fixed-size, `Copy` alternative to `DeepSea` for search and rollouts, which clones without
+/// allocating. Plays by the same rules, but keeps no turn history.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CompactDeepSea {
+    /// A nibble per tile: 0 for empty, otherwise 1 + the treasure's level.
+    path: u128,
+    path_len: u8,
+    /// A bit per tile.
+    occupied_tiles: u32,
+    players: [CompactPlayer; MAX_PLAYERS],
+    num_players: u8,
+    player_idx: u8,
+    oxygen: u8,
+}
+
+impl CompactDeepSea {
+    pub fn new(path: &[Tile], num_players: usize) -> DeepSeaResult<Self> {
+        if path.is_empty() || path.len() > MAX_PATH_LENGTH {
+            return Err(DeepSeaError::InvalidConfig(format!(
+                "Compact paths hold 1 to {MAX_PATH_LENGTH} tiles, not {}",
+                path.len()
+            )));
+        }
+        if num_players == 0 || num_players > MAX_PLAYERS {
+            return Err(DeepSeaError::InvalidConfig(format!(
+                "Compact games hold 1 to {MAX_PLAYERS} players, not {num_players}"
+            )));
+        }
+        let mut deep_sea = Self {
+            path: 0,
+            path_len: path.len() as u8,
+            occupied_tiles: 0,
+            players: [CompactPlayer::NEW; MAX_PLAYERS],
+            num_players: num_players as u8,
+            player_idx: 0,
+            oxygen: DeepSea::OXYGEN as u8,
+        };
+        for (idx, &tile) in path.iter().enumerate() {
+            deep_sea.set_tile(idx, tile);
+        }
+        Ok(deep_sea)
+    }
+
+    pub fn path_len(&self) -> usize {
+        self.path_len as usize
+    }
+
+    pub fn tile(&self, idx: usize) -> Tile {
+        debug_assert!(idx < self.path_len());
+        match (self.path >> (4 * idx)) as usize & 0xf {
+            0 => Tile::Empty,
+            code => Tile::Treasure(TREASURES[code - 1]),
+        }
+    }
+
+    fn set_tile(&mut self, idx: usize, tile: Tile) {
+        let code = match tile {
+            Tile::Empty => 0,
+            Tile::Treasure(treasure) => 1 + treasure_code(treasure) as u128,
+        };
+        self.path = (self.path & !(0xf << (4 * idx))) | (code << (4 * idx));
+    }
+
+    pub fn num_players(&self) -> usize {
+        self.num_players as usize
+    }
+
+    pub fn player_idx(&self) -> usize {
+        self.player_idx as usize
+    }
+
+    pub fn oxygen(&self) -> u32 {
+        self.oxygen as u32
+    }
+
+    pub fn direction(&self, player_idx: usize) -> DiveDirection {
+        self.players[player_idx].direction
+    }
+
+    pub fn position(&self, player_idx: usize) -> Position {
+        self.players[player_idx].position()
+    }
+
+    pub fn held_treasures(&self, player_idx: usize) -> impl Iterator<Item = Treasure> + use<> {
+        self.players[player_idx].held_treasures()
+    }
+
+    pub fn num_held(&self, player_idx: usize) -> usize {
+        self.players[player_idx].num_held as usize
+    }
+
+    fn at_end(&self, position: Position) -> bool {
+        position == Position::Diving(self.path_len() - 1)
+    }
+
+    pub fn occupied(&self, position: Position) -> bool {
+        match position {
+            Position::Diving(index) => self.occupied_tiles & (1 << index) != 0,
+            Position::ReturnedToSubmarine | Position::WaitingToDive => false,
+        }
+    }
+
+    fn leave_tile(&mut self, position: Position) {
+        if let Position::Diving(index) = position {
+            self.occupied_tiles &= !(1 << index);
+        }
+    }
+
+    fn enter_tile(&mut self, position: Position) {
+        if let Position::Diving(index) = position {
+            self.occupied_tiles |= 1 << index;
+        }
+    }
+
+    pub fn done(&self) -> bool {
+        self.oxygen == 0
+            || self.players[..self.num_players()]
+                .iter()
+                .all(|player| player.position == RETURNED_TO_SUBMARINE)
+    }
+
+    pub fn take_oxygen(&mut self) {
+        let player = &self.players[self.player_idx()];
+        self.oxygen = self.oxygen.saturating_sub(player.num_held);
+    }
+
+    pub fn move_player(&mut self, direction: DiveDirection, dice_roll: u32) -> DeepSeaResult {
+        let player = self.players[self.player_idx()];
+        match (player.position(), direction) {
+            (Position::ReturnedToSubmarine, _) => {
+                return Err(DeepSeaError::MoveAfterReturning {
+                    player_idx: self.player_idx(),
+                    state: Some(Box::new(*self)),
+                });
+            }
+            (Position::WaitingToDive, DiveDirection::Up) => {
+                return Err(DeepSeaError::IllegalDirection {
+                    player_idx: self.player_idx(),
+                    direction,
+                    position: player.position(),
+                    state: Some(Box::new(*self)),
+                });
+            }
+            _ => {}
+        }
+        let mut dice_roll = dice_roll.saturating_sub(player.num_held as u32);
+
+        let mut cur_player_pos = player.position();
+        let mut player_pos = cur_player_pos;
+        while dice_roll > 0 && cur_player_pos != Position::ReturnedToSubmarine {
+            if direction == DiveDirection::Down && self.at_end(cur_player_pos) {
+                break;
+            }
+
+            cur_player_pos = cur_player_pos
+                .advance(direction)
+                .expect("Moves no diver can make are rejected above");
+            if !self.occupied(cur_player_pos) {
+                dice_roll -= 1;
+                player_pos = cur_player_pos;
+            }
+        }
+
+        self.leave_tile(player.position());
+        self.enter_tile(player_pos);
+        let player = &mut self.players[self.player_idx as usize];
+        player.direction = direction;
+        player.set_position(player_pos);
+        Ok(())
+    }
+
+    pub fn take_treasure(&mut self, treasure: TreasureDecision) -> DeepSeaResult {
+        let player_idx = self.player_idx();
+        let tile_idx = self.position(player_idx).as_diving().unwrap();
+        let tile = self.tile(tile_idx);
+        match treasure {
+            TreasureDecision::Take => {
+                if let Tile::Treasure(treasure) = tile {
+                    self.players[player_idx].hold(treasure)?;
+                    self.set_tile(tile_idx, Tile::Empty);
+                    Ok(())
+                } else {
+                    Err(DeepSeaError::TakeFromEmptyTile {
+                        player_idx,
+                        tile_idx,
+                        state: Some(Box::new(*self)),
+                    })
+                }
+            }
+            TreasureDecision::Return(treasure) => {
+                if tile != Tile::Empty {
+                    Err(DeepSeaError::ReturnOntoOccupiedTile {
+                        player_idx,
+                        tile_idx,
+                        tile,
+                        state: Some(Box::new(*self)),
+                    })
+                } else if self.players[player_idx].drop_treasure(treasure) {
+                    self.set_tile(tile_idx, Tile::Treasure(treasure));
+                    Ok(())
+                } else {
+                    Err(DeepSeaError::TreasureNotHeld {
+                        player_idx,
+                        treasure,
+                        held: self.held_treasures(player_idx).collect(),
+                        state: Some(Box::new(*self)),
+                    })
+                }
+            }
+            TreasureDecision::Ignore => Ok(()),
+        }
+    }
+
+    pub fn next_player(&mut self) {
+        self.player_idx = (self.player_idx + 1) % self.num_players;
+    }
+
+    /// Directions the current player may choose, as `DeepSea::legal_directions`.
+    pub fn legal_directions(&self) -> &'static [DiveDirection] {
+        self.position(self.player_idx())
+            .legal_directions(self.path_len())
+    }
+
+    /// Treasure decisions the current player may make on the tile they occupy, as
+    /// `DeepSea::legal_treasure_decisions` but with returns in order of level.
+    pub fn legal_treasure_decisions(&self) -> impl Iterator<Item = TreasureDecision> + use<> {
+        let player = self.players[self.player_idx()];
+        let tile = player
+            .position()
+            .as_diving()
+            .map(|tile_idx| self.tile(tile_idx));
+        let returnable = TREASURES.into_iter().filter(move |&treasure| {
+            tile == Some(Tile::Empty) && player.held_treasures().any(|t| t == treasure)
+        });
+        std::iter::once(TreasureDecision::Ignore)
+            .chain(matches!(tile, Some(Tile::Treasure(_))).then_some(TreasureDecision::Take))
+            .chain(returnable.map(TreasureDecision::Return))
+    }
+}
+
+impl TryFrom<&DeepSea> for CompactDeepSea {
+    type Error = DeepSeaError;
+
+    fn try_from(deep_sea: &DeepSea) -> DeepSeaResult<Self> {
+        let mut compact = Self::new(&deep_sea.path, deep_sea.players.len())?;
+        for (compact_player, player) in compact.players.iter_mut().zip(&deep_sea.players) {
+            compact_player.direction = player.direction;
+            compact_player.set_position(player.position);
+            for &treasure in &player.held_treasures {
+                compact_player.hold(treasure)?;
+            }
+        }
+        compact.occupied_tiles = deep_sea
+            .occupied_tiles
+            .iter()
+            .fold(0, |tiles, idx| tiles | 1 << idx);
+        compact.player_idx = deep_sea.player_idx as u8;
+        compact.oxygen = deep_sea.oxygen as u8;
+        Ok(compact)
+    }
+}
+
+impl From<&CompactDeepSea> for DeepSea {
+    /// The players' turn history starts out empty.
+    fn from(compact: &CompactDeepSea) -> Self {
+        DeepSea::from_parts(
+            (0..compact.path_len())
+                .map(|idx| compact.tile(idx))
+                .collect(),
+            (0..compact.num_players()).map(|idx| {
+                (
+                    compact.direction(idx),
+                    compact.position(idx),
+                    compact.held_treasures(idx).collect(),
+                )
+            }),
+            compact.player_idx(),
+            compact.oxygen(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+    use rand::{Rng, SeedableRng, rngs::StdRng, seq::IndexedRandom};
+
+    use crate::{
+        deep_sea::{
+            DeepSea, DiveDirection, Position, Tile,
+            compact::{CompactDeepSea, MAX_PATH_LENGTH},
+        },
+        deep_sea_vectorization::ActionMask,
+        engine::Engine,
+        solver::TreasureDecision,
+        treasure::Treasure,
+    };
+
+    fn assert_same(compact: &CompactDeepSea, deep_sea: &DeepSea) {
+        assert_eq!(
+            (0..compact.path_len())
+                .map(|idx| compact.tile(idx))
+                .collect::<Vec<_>>(),
+            deep_sea.path()
+        );
+        assert_eq!(compact.num_players(), deep_sea.players().len());
+        for (idx, player) in deep_sea.players().iter().enumerate() {
+            assert_eq!(compact.direction(idx), player.direction());
+            assert_eq!(compact.position(idx), player.position());
+            assert!(
+                compact
+                    .held_treasures(idx)
+                    .eq(player.held_treasures().iter().copied())
+            );
+            assert_eq!(
+                compact.occupied(player.position()),
+                deep_sea.occupied(player.position())
+            );
+        }
+        assert_eq!(
+            (0..compact.path_len())
+                .filter(|&idx| compact.occupied(Position::Diving(idx)))
+                .collect::<Vec<_>>(),
+            deep_sea.occupied_tiles().iter().collect::<Vec<_>>()
+        );
+        assert_eq!(compact.player_idx(), deep_sea.player_idx());
+        assert_eq!(compact.oxygen(), deep_sea.oxygen());
+        assert_eq!(compact.done(), deep_sea.done());
+    }
+
+    #[test]
+    fn test_matches_deep_sea_on_random_games() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for game in 0..200 {
+            let num_players = 1 + game % 6;
+            let mut deep_sea = DeepSea::new(Engine::default_path(), num_players);
+            let mut compact = CompactDeepSea::try_from(&deep_sea).unwrap();
+            while !deep_sea.done() {
+                if deep_sea.players()[deep_sea.player_idx()].position()
+                    != Position::ReturnedToSubmarine
+                {
+                    deep_sea.take_oxygen();
+                    compact.take_oxygen();
+
+                    assert_eq!(compact.legal_directions(), deep_sea.legal_directions());
+                    let direction = *deep_sea.legal_directions().choose(&mut rng).unwrap();
+                    let dice_roll = rng.random_range(2..=6);
+                    deep_sea.move_player(direction, dice_roll).unwrap();
+                    compact.move_player(direction, dice_roll).unwrap();
+
+                    let player = &deep_sea.players()[deep_sea.player_idx()];
+                    if let Position::Diving(_) = player.position() {
+                        assert_eq!(
+                            ActionMask::from_actions(compact.legal_treasure_decisions().map_into()),
+                            ActionMask::treasure_decisions(&deep_sea)
+                        );
+                        // Illegal decisions must be rejected by both.
+                        let decision = if rng.random_bool(0.1) {
+                            *[
+                                TreasureDecision::Take,
+                                TreasureDecision::Return(Treasure::Four),
+                            ]
+                            .choose(&mut rng)
+                            .unwrap()
+                        } else {
+                            *deep_sea
+                                .legal_treasure_decisions()
+                                .choose(&mut rng)
+                                .unwrap()
+                        };
+                        assert_eq!(
+                            deep_sea.take_treasure(decision).is_ok(),
+                            compact.take_treasure(decision).is_ok()
+                        );
+                    }
+                }
+                deep_sea.next_player();
+                compact.next_player();
+                assert_same(&compact, &deep_sea);
+            }
+            assert_eq!(DeepSea::from(&compact).done(), deep_sea.done());
+            assert_eq!(CompactDeepSea::try_from(&deep_sea).unwrap(), compact);
+        }
+    }
+
+    #[test]
+    fn test_conversion_round_trip() {
+        let mut deep_sea = DeepSea::new(Engine::default_path(), 3);
+        deep_sea.move_player(DiveDirection::Down, 4).unwrap();
+        deep_sea.take_treasure(TreasureDecision::Take).unwrap();
+        deep_sea.next_player();
+        deep_sea.move_player(DiveDirection::Down, 6).unwrap();
+        deep_sea.take_treasure(TreasureDecision::Take).unwrap();
+
+        let compact = CompactDeepSea::try_from(&deep_sea).unwrap();
+        assert_same(&compact, &deep_sea);
+        let restored = DeepSea::from(&compact);
+        assert_same(&compact, &restored);
+        assert!(
+            restored
+                .players()
+                .iter()
+                .all(|player| player.turns().num_turns() == 0)
+        );
+        assert_eq!(CompactDeepSea::try_from(&restored).unwrap(), compact);
+    }
+
+    #[test]
+    fn test_held_treasures_keep_their_order() {
+        let path = [Tile::Empty; 5];
+        let mut compact = CompactDeepSea::new(&path, 1).unwrap();
+        for treasure in [Treasure::Two, Treasure::Four, Treasure::Two, Treasure::One] {
+            compact.players[0].hold(treasure).unwrap();
+        }
+        assert!(compact.players[0].drop_treasure(Treasure::Two));
+        assert!(!compact.players[0].drop_treasure(Treasure::Three));
+        assert_eq!(
+            compact.held_treasures(0).collect::<Vec<_>>(),
+            vec![Treasure::Four, Treasure::Two, Treasure::One]
+        );
+    }
+
+    #[test]
+    fn test_rejects_oversized_games() {
+        assert!(CompactDeepSea::new(&[Tile::Empty; MAX_PATH_LENGTH + 1], 2).is_err());
+        assert!(CompactDeepSea::new(&[Tile::Empty; 4], 7).is_err());
+        assert!(
+            CompactDeepSea::try_from(&DeepSea::new(vec![Tile::Empty; MAX_PATH_LENGTH + 1], 2))
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,226 @@
+use crate::{
+    deep_sea::{DeepSea, DiveDirection, Position, Tile, TurnHistory},
+    error::DeepSeaResult,
+    solver::TreasureDecision,
+    treasure::Treasure,
+};
+
+/// One of the `DeepSea` methods that advance the game, with its arguments.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Move {
+    TakeOxygen,
+    MovePlayer {
+        direction: DiveDirection,
+        dice_roll: u32,
+    },
+    TakeTreasure(TreasureDecision),
+    NextPlayer,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum HeldChange {
+    Unchanged,
+    Took,
+    /// The index the returned treasure was held at.
+    Returned(usize, Treasure),
+}
+
+/// What a `Move` overwrote, so that `DeepSea::undo` can restore it exactly.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MoveRecord {
+    mv: Move,
+    player_idx: usize,
+    oxygen: u32,
+    direction: DiveDirection,
+    position: Position,
+    turns: TurnHistory,
+    held: HeldChange,
+    /// The tile a treasure decision was made on, as it was before.
+    tile: Option<(usize, Tile)>,
+    zobrist_hash: u64,
+}
+
+impl MoveRecord {
+    pub fn mv(&self) -> Move {
+        self.mv
+    }
+}
+
+impl DeepSea {
+    /// Applies `mv` in place. A move the game rejects leaves it unchanged.
+    pub fn apply(&mut self, mv: Move) -> DeepSeaResult<MoveRecord> {
+        let player = &self.players[self.player_idx];
+        let mut record = MoveRecord {
+            mv,
+            player_idx: self.player_idx,
+            oxygen: self.oxygen,
+            direction: player.direction,
+            position: player.position,
+            turns: player.turns,
+            held: HeldChange::Unchanged,
+            tile: None,
+            zobrist_hash: self.zobrist_hash,
+        };
+        match mv {
+            Move::TakeOxygen => self.take_oxygen(),
+            Move::MovePlayer {
+                direction,
+                dice_roll,
+            } => self.move_player(direction, dice_roll)?,
+            Move::TakeTreasure(decision) => {
+                record.held = match decision {
+                    TreasureDecision::Take => HeldChange::Took,
+                    TreasureDecision::Return(treasure) => player
+                        .held_treasures
+                        .iter()
+                        .position(|&held| held == treasure)
+                        .map_or(HeldChange::Unchanged, |idx| {
+                            HeldChange::Returned(idx, treasure)
+                        }),
+                    TreasureDecision::Ignore => HeldChange::Unchanged,
+                };
+                record.tile = player
+                    .position
+                    .as_diving()
+                    .map(|tile_idx| (tile_idx, self.path[tile_idx]));
+                self.take_treasure(decision)?;
+            }
+            Move::NextPlayer => self.next_player(),
+        }
+        Ok(record)
+    }
+
+    /// Reverses the move `record` was returned for. Records have to be undone in the reverse of
+    /// the order they were applied in.
+    pub fn undo(&mut self, record: MoveRecord) {
+        self.leave_tile(self.players[record.player_idx].position);
+        self.enter_tile(record.position);
+
+        let player = &mut self.players[record.player_idx];
+        player.direction = record.direction;
+        player.position = record.position;
+        player.turns = record.turns;
+        match record.held {
+            HeldChange::Unchanged => {}
+            HeldChange::Took => {
+                player.held_treasures.pop();
+            }
+            HeldChange::Returned(idx, treasure) => player.held_treasures.insert(idx, treasure),
+        }
+        if let Some((tile_idx, tile)) = record.tile {
+            self.path[tile_idx] = tile;
+        }
+
+        self.oxygen = record.oxygen;
+        self.player_idx = record.player_idx;
+        self.zobrist_hash = record.zobrist_hash;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng, rngs::StdRng, seq::IndexedRandom};
+
+    use crate::{
+        deep_sea::{
+            DeepSea, DiveDirection, Position, Tile,
+            moves::{Move, MoveRecord},
+        },
+        engine::Engine,
+        solver::TreasureDecision,
+        treasure::Treasure,
+    };
+
+    fn random_move(deep_sea: &DeepSea, rng: &mut StdRng) -> Move {
+        match rng.random_range(0..4) {
+            0 => Move::TakeOxygen,
+            1 => Move::MovePlayer {
+                direction: *deep_sea.legal_directions().choose(rng).unwrap(),
+                dice_roll: rng.random_range(2..=6),
+            },
+            2 => Move::TakeTreasure(
+                deep_sea
+                    .legal_treasure_decisions()
+                    .choose(rng)
+                    .copied()
+                    .unwrap_or(TreasureDecision::Ignore),
+            ),
+            _ => Move::NextPlayer,
+        }
+    }
+
+    #[test]
+    fn test_undo_restores_every_state() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for game in 0..50 {
+            let mut deep_sea = DeepSea::new(Engine::default_path(), 2 + game % 5);
+            let mut history: Vec<(DeepSea, MoveRecord)> = vec![];
+            while !deep_sea.done() && history.len() < 500 {
+                let before = deep_sea.clone();
+                let mv = random_move(&deep_sea, &mut rng);
+                // Same as `Engine`, which only moves players still in the game and only asks
+                // players on a tile for treasure decisions.
+                let skip = match (deep_sea.players()[deep_sea.player_idx()].position(), mv) {
+                    (Position::ReturnedToSubmarine, Move::MovePlayer { .. }) => true,
+                    (Position::Diving(_), _) => false,
+                    (_, mv) => matches!(mv, Move::TakeTreasure(_)),
+                };
+                if skip {
+                    continue;
+                }
+                match deep_sea.apply(mv) {
+                    Ok(record) => {
+                        assert_eq!(record.mv(), mv);
+                        history.push((before, record));
+                    }
+                    Err(_) => assert_eq!(deep_sea, before),
+                }
+            }
+            while let Some((before, record)) = history.pop() {
+                deep_sea.undo(record);
+                assert_eq!(deep_sea, before);
+            }
+        }
+    }
+
+    #[test]
+    fn test_undo_returned_treasure() {
+        let path = vec![
+            Tile::Treasure(Treasure::One),
+            Tile::Treasure(Treasure::Two),
+            Tile::Empty,
+        ];
+        let mut deep_sea = DeepSea::new(path, 1);
+        for mv in [
+            Move::MovePlayer {
+                direction: DiveDirection::Down,
+                dice_roll: 1,
+            },
+            Move::TakeTreasure(TreasureDecision::Take),
+            Move::MovePlayer {
+                direction: DiveDirection::Down,
+                dice_roll: 2,
+            },
+            Move::TakeTreasure(TreasureDecision::Take),
+            Move::MovePlayer {
+                direction: DiveDirection::Down,
+                dice_roll: 3,
+            },
+        ] {
+            deep_sea.apply(mv).unwrap();
+        }
+        let before = deep_sea.clone();
+        let record = deep_sea
+            .apply(Move::TakeTreasure(TreasureDecision::Return(Treasure::One)))
+            .unwrap();
+        assert_eq!(deep_sea.players()[0].held_treasures(), [Treasure::Two]);
+        assert_eq!(deep_sea.path()[2], Tile::Treasure(Treasure::One));
+
+        deep_sea.undo(record);
+        assert_eq!(deep_sea, before);
+        assert_eq!(
+            deep_sea.players()[0].held_treasures(),
+            [Treasure::One, Treasure::Two]
+        );
+    }
+}
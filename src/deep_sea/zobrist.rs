@@ -0,0 +1,228 @@
+use strum::EnumCount;
+
+use crate::{
+    deep_sea::{DeepSea, DiveDirection, Position, Tile},
+    treasure::Treasure,
+};
+
+/// Scrambles a feature id into its key. Keys are derived rather than drawn from a table so
+/// that they are the same in every process and cover paths and tables of any size.
+fn mix(feature: u64) -> u64 {
+    // splitmix64.
+    let mut z = feature.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Tags each kind of feature so that no two kinds share an id.
+fn feature_key(kind: u64, a: usize, b: usize, c: usize) -> u64 {
+    mix(kind << 60 | (a as u64) << 40 | (b as u64) << 20 | c as u64)
+}
+
+pub(super) fn tile_key(tile_idx: usize, tile: Tile) -> u64 {
+    match tile {
+        Tile::Empty => 0,
+        Tile::Treasure(treasure) => feature_key(1, tile_idx, treasure as usize, 0),
+    }
+}
+
+/// Only a diving player's direction matters, since it decides whether they are asked for one.
+pub(super) fn player_key(player_idx: usize, position: Position, direction: DiveDirection) -> u64 {
+    let (position, forced_up) = match position {
+        Position::WaitingToDive => (0, false),
+        Position::ReturnedToSubmarine => (1, false),
+        Position::Diving(depth) => (2 + depth, direction == DiveDirection::Up),
+    };
+    feature_key(2, player_idx, position, usize::from(forced_up))
+}
+
+/// Held treasures hash as a multiset: the `count`th copy of `treasure` held has its own key.
+pub(super) fn held_key(player_idx: usize, treasure: Treasure, count: usize) -> u64 {
+    feature_key(3, player_idx, treasure as usize, count)
+}
+
+pub(super) fn oxygen_key(oxygen: u32) -> u64 {
+    feature_key(4, oxygen as usize, 0, 0)
+}
+
+pub(super) fn turn_key(player_idx: usize) -> u64 {
+    feature_key(5, player_idx, 0, 0)
+}
+
+impl DeepSea {
+    /// The Zobrist hash of the whole state, which `zobrist_hash` keeps up to date incrementally.
+    pub(super) fn compute_zobrist_hash(&self) -> u64 {
+        let tiles = self
+            .path
+            .iter()
+            .enumerate()
+            .map(|(idx, &tile)| tile_key(idx, tile));
+        let players = self.players.iter().enumerate().flat_map(|(idx, player)| {
+            let mut counts = [0; Treasure::COUNT];
+            std::iter::once(player_key(idx, player.position, player.direction)).chain(
+                player.held_treasures.iter().map(move |&treasure| {
+                    counts[treasure as usize] += 1;
+                    held_key(idx, treasure, counts[treasure as usize])
+                }),
+            )
+        });
+        tiles
+            .chain(players)
+            .chain([oxygen_key(self.oxygen), turn_key(self.player_idx)])
+            .fold(0, |hash, key| hash ^ key)
+    }
+
+    /// Identifies the state up to differences that cannot affect the rest of the game: the order
+    /// treasures were picked up in, the direction of players not diving, and turn history.
+    /// States with equal keys have equal `zobrist_hash`es.
+    pub fn canonical_key(&self) -> CanonicalKey {
+        CanonicalKey {
+            path: self.path.clone(),
+            players: self
+                .players
+                .iter()
+                .map(|player| {
+                    let mut held = [0; Treasure::COUNT];
+                    for &treasure in &player.held_treasures {
+                        held[treasure as usize] += 1;
+                    }
+                    CanonicalPlayer {
+                        position: player.position,
+                        forced_up: matches!(player.position, Position::Diving(_))
+                            && player.direction == DiveDirection::Up,
+                        held,
+                    }
+                })
+                .collect(),
+            oxygen: self.oxygen,
+            player_idx: self.player_idx,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct CanonicalPlayer {
+    position: Position,
+    forced_up: bool,
+    /// Count of each treasure level held.
+    held: [u8; Treasure::COUNT],
+}
+
+/// See `DeepSea::canonical_key`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CanonicalKey {
+    path: Vec<Tile>,
+    players: Vec<CanonicalPlayer>,
+    oxygen: u32,
+    player_idx: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use rand::{Rng, SeedableRng, rngs::StdRng, seq::IndexedRandom};
+
+    use crate::{
+        deep_sea::{DeepSea, DiveDirection, Position, Tile, compact::CompactDeepSea},
+        engine::Engine,
+        solver::TreasureDecision,
+        treasure::Treasure,
+    };
+
+    #[test]
+    fn test_incremental_hash_matches_full_hash() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut hashes = HashSet::new();
+        let mut keys = HashSet::new();
+        for game in 0..50 {
+            let mut deep_sea = DeepSea::new(Engine::default_path(), 2 + game % 5);
+            while !deep_sea.done() {
+                let player = &deep_sea.players()[deep_sea.player_idx()];
+                if player.position() != Position::ReturnedToSubmarine {
+                    deep_sea.take_oxygen();
+                    let direction = *deep_sea.legal_directions().choose(&mut rng).unwrap();
+                    deep_sea
+                        .move_player(direction, rng.random_range(2..=6))
+                        .unwrap();
+                    if let Position::Diving(_) =
+                        deep_sea.players()[deep_sea.player_idx()].position()
+                    {
+                        let decision = *deep_sea
+                            .legal_treasure_decisions()
+                            .choose(&mut rng)
+                            .unwrap();
+                        deep_sea.take_treasure(decision).unwrap();
+                    }
+                }
+                deep_sea.next_player();
+                assert_eq!(deep_sea.zobrist_hash(), deep_sea.compute_zobrist_hash());
+                hashes.insert(deep_sea.zobrist_hash());
+                keys.insert(deep_sea.canonical_key());
+            }
+        }
+        // Distinct states hash apart.
+        assert_eq!(hashes.len(), keys.len());
+    }
+
+    #[test]
+    fn test_transpositions_hash_equal() {
+        let path = vec![
+            Tile::Treasure(Treasure::One),
+            Tile::Treasure(Treasure::Two),
+            Tile::Empty,
+            Tile::Empty,
+        ];
+        // Picks up both treasures, in either order, and ends on the same tile.
+        let mut deeper_first = DeepSea::new(path.clone(), 1);
+        deeper_first.move_player(DiveDirection::Down, 2).unwrap();
+        deeper_first.take_treasure(TreasureDecision::Take).unwrap();
+        deeper_first.move_player(DiveDirection::Up, 2).unwrap();
+        deeper_first.take_treasure(TreasureDecision::Take).unwrap();
+        deeper_first.move_player(DiveDirection::Down, 5).unwrap();
+        deeper_first
+            .take_treasure(TreasureDecision::Ignore)
+            .unwrap();
+
+        let mut shallower_first = DeepSea::new(path, 1);
+        shallower_first.move_player(DiveDirection::Down, 1).unwrap();
+        shallower_first
+            .take_treasure(TreasureDecision::Take)
+            .unwrap();
+        shallower_first.move_player(DiveDirection::Down, 2).unwrap();
+        shallower_first
+            .take_treasure(TreasureDecision::Take)
+            .unwrap();
+        shallower_first.move_player(DiveDirection::Down, 4).unwrap();
+
+        assert_ne!(
+            deeper_first.players()[0].held_treasures(),
+            shallower_first.players()[0].held_treasures()
+        );
+        assert_eq!(
+            deeper_first.canonical_key(),
+            shallower_first.canonical_key()
+        );
+        assert_eq!(deeper_first.zobrist_hash(), shallower_first.zobrist_hash());
+
+        shallower_first.take_oxygen();
+        assert_ne!(
+            deeper_first.canonical_key(),
+            shallower_first.canonical_key()
+        );
+        assert_ne!(deeper_first.zobrist_hash(), shallower_first.zobrist_hash());
+    }
+
+    #[test]
+    fn test_hash_survives_compact_round_trip() {
+        let mut deep_sea = DeepSea::new(Engine::default_path(), 3);
+        deep_sea.move_player(DiveDirection::Down, 4).unwrap();
+        deep_sea.take_treasure(TreasureDecision::Take).unwrap();
+        deep_sea.next_player();
+
+        let restored = DeepSea::from(&CompactDeepSea::try_from(&deep_sea).unwrap());
+        assert_eq!(restored.zobrist_hash(), deep_sea.zobrist_hash());
+        assert_eq!(restored.canonical_key(), deep_sea.canonical_key());
+    }
+}
@@ -1,6 +1,7 @@
 use crate::{
     deep_sea,
     deep_sea::*,
+    error::{DeepSeaError, DeepSeaResult},
     ml::vectorization::*,
     solver::TreasureDecision,
     treasure::{MAX_NUM_TREASURES, Treasure},
@@ -11,17 +12,139 @@ use strum::EnumCount;
 
 pub const TREASURE_DECISION_COUNT: usize = 2 + Treasure::COUNT;
 pub const POSITION_COUNT: usize = Position::COUNT;
+/// Bumped whenever the features fed to models change, so that models trained on an older
+/// layout are not silently fed a new one.
+pub const ENCODING_VERSION: u32 = 4;
 
+/// Length of `Engine::default_path`.
+const DEFAULT_PATH_LENGTH: usize = 32;
+
+const TREASURES: [Treasure; Treasure::COUNT] = [
+    Treasure::One,
+    Treasure::Two,
+    Treasure::Three,
+    Treasure::Four,
+];
+const DIVE_DIRECTIONS: [DiveDirection; DiveDirection::COUNT] =
+    [DiveDirection::Down, DiveDirection::Up];
+
+/// Chooses the `Encoding` of each kind of feature. Actions and binary features are always
+/// one-hot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct EncoderConfig {
+    /// Held treasures.
+    pub treasure: Encoding,
+    /// Path tiles, with empty tiles encoded apart from every treasure level.
+    pub tile: Encoding,
+    /// Player positions.
+    pub position: Encoding,
+    pub oxygen: Encoding,
+    /// Longest path a position is encoded for, which bounds depths when they are one-hot or
+    /// normalized.
+    pub path_length: usize,
+    /// Player slots of a padded state.
+    pub max_players: usize,
+    /// Pads states to `path_length` tiles and `max_players` players, with a presence flag per
+    /// tile and player slot, so that their width does not depend on the game being played.
+    pub padded: bool,
+    /// Number of each player's latest turns encoded along with a summary of their whole game,
+    /// or `None` to encode only the current snapshot.
+    pub history_turns: Option<usize>,
+}
+
+impl EncoderConfig {
+    /// Every kind of feature encoded with `encoding`.
+    pub fn uniform(encoding: Encoding) -> Self {
+        Self {
+            treasure: encoding,
+            tile: encoding,
+            position: encoding,
+            oxygen: encoding,
+            ..Self::default()
+        }
+    }
+
+    /// The raw encoding padded to a fixed width, fed to models so that one model can play any
+    /// number of players on any path.
+    pub fn padded() -> Self {
+        Self {
+            padded: true,
+            ..Self::default()
+        }
+    }
+
+    /// Also encodes each player's latest `turns` turns and their history so far. At most
+    /// `MAX_RECENT_TURNS` turns are kept.
+    pub fn with_history(self, turns: usize) -> Self {
+        assert!(
+            turns <= MAX_RECENT_TURNS,
+            "Only {MAX_RECENT_TURNS} turns are kept"
+        );
+        Self {
+            history_turns: Some(turns),
+            ..self
+        }
+    }
+
+    /// Counts in a player's history are normalized by `path_length` if positions are, and raw
+    /// otherwise.
+    fn count_encoding(&self) -> Encoding {
+        match self.position {
+            Encoding::Normalized => Encoding::Normalized,
+            _ => Encoding::Raw,
+        }
+    }
+}
+
+impl Default for EncoderConfig {
+    fn default() -> Self {
+        Self {
+            treasure: Encoding::Raw,
+            tile: Encoding::Raw,
+            position: Encoding::Raw,
+            oxygen: Encoding::Raw,
+            path_length: DEFAULT_PATH_LENGTH,
+            max_players: MAX_PLAYERS,
+            padded: false,
+            history_turns: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Path {
     pub tiles: Vec<Tile>,
     pub occupied: BitSet,
 }
 
-#[derive(Clone, Debug)]
+/// What a player has done so far, which hints at what they will do next.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct History {
+    /// Latest turn first.
+    pub recent_turns: Vec<Turn>,
+    /// Turns taken before turning around, if the player has.
+    pub turned_around: Option<usize>,
+    pub treasures_taken: usize,
+    pub treasures_returned: usize,
+}
+
+impl From<&TurnHistory> for History {
+    fn from(turns: &TurnHistory) -> Self {
+        Self {
+            recent_turns: turns.recent().collect(),
+            turned_around: turns.turned_around(),
+            treasures_taken: turns.treasures_taken(),
+            treasures_returned: turns.treasures_returned(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Player {
     pub direction: DiveDirection,
     pub position: Position,
     pub held_treasures: Vec<Treasure>,
+    pub history: History,
 }
 
 impl Player {
@@ -30,6 +153,7 @@ impl Player {
             direction: DiveDirection::Down,
             position: Position::WaitingToDive,
             held_treasures: vec![],
+            history: History::default(),
         }
     }
 }
@@ -42,18 +166,89 @@ impl Default for Player {
 
 impl From<deep_sea::Player> for Player {
     fn from(ds_player: deep_sea::Player) -> Self {
+        Self::from(&ds_player)
+    }
+}
+
+impl From<&deep_sea::Player> for Player {
+    fn from(ds_player: &deep_sea::Player) -> Self {
         Self {
             direction: ds_player.direction(),
             position: ds_player.position(),
-            held_treasures: ds_player.held_treasures().to_vec().clone(),
+            held_treasures: ds_player.held_treasures().to_vec(),
+            history: History::from(ds_player.turns()),
         }
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct DeepSeaState {
     pub path: Path,
     pub players: Vec<Player>,
     pub oxygen: u16,
+    /// Index into `players` of the player to act.
+    pub player_idx: usize,
+}
+
+impl From<&DeepSea> for DeepSeaState {
+    fn from(deep_sea: &DeepSea) -> Self {
+        Self {
+            path: Path {
+                tiles: deep_sea.path().to_vec(),
+                occupied: deep_sea.occupied_tiles().clone(),
+            },
+            players: deep_sea.players().iter().map(Player::from).collect(),
+            oxygen: deep_sea.oxygen() as u16,
+            player_idx: deep_sea.player_idx(),
+        }
+    }
+}
+
+/// Maps seats between absolute turn order and the view of one player, in which that player
+/// comes first followed by their opponents in turn order. Observing every position from the
+/// acting player's perspective lets one network play any seat.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Perspective {
+    seat: usize,
+    num_players: usize,
+}
+
+impl Perspective {
+    pub fn new(seat: usize, num_players: usize) -> Self {
+        debug_assert!(seat < num_players);
+        Self { seat, num_players }
+    }
+
+    /// The perspective of the player to act in `deep_sea`.
+    pub fn acting(deep_sea: &DeepSea) -> Self {
+        Self::new(deep_sea.player_idx(), deep_sea.players().len())
+    }
+
+    pub fn seat(&self) -> usize {
+        self.seat
+    }
+
+    pub fn to_relative(&self, absolute_seat: usize) -> usize {
+        (absolute_seat + self.num_players - self.seat) % self.num_players
+    }
+
+    pub fn to_absolute(&self, relative_seat: usize) -> usize {
+        (relative_seat + self.seat) % self.num_players
+    }
+
+    /// Vectorizable state of `deep_sea` with players reordered from this perspective.
+    pub fn observe(&self, deep_sea: &DeepSea) -> DeepSeaState {
+        let mut state = DeepSeaState::from(deep_sea);
+        state.players.rotate_left(self.seat);
+        state.player_idx = self.to_relative(state.player_idx);
+        state
+    }
+
+    /// Decodes an action slot chosen by a network fed `observe`'s output. Action slots carry no
+    /// seat, so this is the same for every perspective.
+    pub fn decode_action(&self, index: usize) -> Option<DeepSeaAction> {
+        DeepSeaAction::from_index(index)
+    }
 }
 
 impl Default for DeepSeaState {
@@ -72,15 +267,46 @@ impl Default for DeepSeaState {
             path,
             players: vec![Player::new(); 6],
             oxygen: DeepSea::OXYGEN as u16,
+            player_idx: 0,
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum DeepSeaAction {
     TreasureDecision(TreasureDecision),
     DiveDirection(DiveDirection),
 }
 
+impl DeepSeaAction {
+    /// Slot of this action in its one-hot encoding.
+    pub fn index(&self) -> usize {
+        match self {
+            DeepSeaAction::TreasureDecision(td) => match td {
+                TreasureDecision::Ignore => 0,
+                TreasureDecision::Take => 1,
+                TreasureDecision::Return(t) => 2 + *t as usize,
+            },
+            DeepSeaAction::DiveDirection(dd) => TREASURE_DECISION_COUNT + *dd as usize,
+        }
+    }
+
+    /// Inverse of `index`.
+    pub fn from_index(index: usize) -> Option<Self> {
+        match index {
+            0 => Some(TreasureDecision::Ignore.into()),
+            1 => Some(TreasureDecision::Take.into()),
+            2..TREASURE_DECISION_COUNT => {
+                Some(TreasureDecision::Return(TREASURES[index - 2]).into())
+            }
+            TREASURE_DECISION_COUNT..DEEP_SEA_ACTION_COUNT => {
+                Some(DIVE_DIRECTIONS[index - TREASURE_DECISION_COUNT].into())
+            }
+            _ => None,
+        }
+    }
+}
+
 impl From<TreasureDecision> for DeepSeaAction {
     fn from(td: TreasureDecision) -> Self {
         Self::TreasureDecision(td)
@@ -93,6 +319,52 @@ impl From<DiveDirection> for DeepSeaAction {
     }
 }
 
+/// Which action slots are legal, in the same order as the one-hot encoding of `DeepSeaAction`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ActionMask([bool; DEEP_SEA_ACTION_COUNT]);
+
+impl ActionMask {
+    pub fn from_actions(actions: impl IntoIterator<Item = DeepSeaAction>) -> Self {
+        let mut mask = Self::default();
+        for action in actions {
+            mask.0[action.index()] = true;
+        }
+        mask
+    }
+
+    /// The directions the player to act in `deep_sea` may choose.
+    pub fn directions(deep_sea: &DeepSea) -> Self {
+        Self::from_actions(deep_sea.legal_directions().into_iter().map_into())
+    }
+
+    /// The treasure decisions the player to act in `deep_sea` may make.
+    pub fn treasure_decisions(deep_sea: &DeepSea) -> Self {
+        Self::from_actions(deep_sea.legal_treasure_decisions().into_iter().map_into())
+    }
+
+    pub fn is_legal(&self, action: DeepSeaAction) -> bool {
+        self.0[action.index()]
+    }
+
+    pub fn as_slice(&self) -> &[bool] {
+        &self.0
+    }
+
+    pub fn actions(&self) -> impl Iterator<Item = DeepSeaAction> + '_ {
+        (0..DEEP_SEA_ACTION_COUNT)
+            .filter(|&idx| self.0[idx])
+            .map(|idx| DeepSeaAction::from_index(idx).unwrap())
+    }
+
+    /// The legal action with the highest of `scores`, which has one score per slot such as a
+    /// network's Q-values or logits.
+    pub fn argmax(&self, scores: &[f32]) -> Option<DeepSeaAction> {
+        debug_assert_eq!(scores.len(), DEEP_SEA_ACTION_COUNT);
+        self.actions()
+            .max_by(|a, b| scores[a.index()].total_cmp(&scores[b.index()]))
+    }
+}
+
 pub struct DeepSeaStateActionPair<'a> {
     pub state: &'a DeepSeaState,
     pub action: &'a DeepSeaAction,
@@ -101,141 +373,883 @@ pub struct DeepSeaStateActionPair<'a> {
 pub const DEEP_SEA_ACTION_COUNT: usize = TREASURE_DECISION_COUNT + DiveDirection::COUNT;
 
 impl Unpackable for Treasure {
-    fn unpack<T: DataType>(&self) -> impl Iterator<Item = T> {
+    fn unpack_with<T: DataType>(&self, config: EncoderConfig) -> impl Iterator<Item = T> {
         // 1-indexed because it's a treasure value.
-        [T::from(1 + *self as u16)].into_iter()
+        let value = 1 + *self as usize;
+        match config.treasure {
+            Encoding::Raw => UnifiedIterator::Opt1(std::iter::once(T::from(value as u16))),
+            Encoding::Normalized => {
+                UnifiedIterator::Opt1(std::iter::once(normalized(value, Treasure::COUNT)))
+            }
+            Encoding::OneHot => UnifiedIterator::Opt2(one_hot(*self as usize, Treasure::COUNT)),
+        }
     }
 
-    fn unpacked_size(&self) -> usize {
-        1
+    fn unpacked_size_with(&self, config: EncoderConfig) -> usize {
+        config.treasure.size(Treasure::COUNT)
     }
 }
 
 impl Unpackable for DiveDirection {
-    fn unpack<T: DataType>(&self) -> impl Iterator<Item = T> {
+    fn unpack_with<T: DataType>(&self, _config: EncoderConfig) -> impl Iterator<Item = T> {
         [T::from(*self as u16)].into_iter()
     }
 
-    fn unpacked_size(&self) -> usize {
+    fn unpacked_size_with(&self, _config: EncoderConfig) -> usize {
         1
     }
 }
 
 impl Unpackable for Position {
-    fn unpack<T: DataType>(&self) -> impl Iterator<Item = T> {
-        let position_val: u16 = match self {
-            Position::Diving(depth) => *depth as u16,
+    fn unpack_with<T: DataType>(&self, config: EncoderConfig) -> impl Iterator<Item = T> {
+        let depth = match self {
+            Position::Diving(depth) => *depth,
             _ => 0,
         };
-        [
-            T::from(position_val),
-            T::from(*self == Position::WaitingToDive),
-            T::from(*self == Position::ReturnedToSubmarine),
-        ]
-        .into_iter()
+        let depth_val = match config.position {
+            Encoding::Normalized => normalized(depth, config.path_length - 1),
+            _ => T::from(depth as u16),
+        };
+        match config.position {
+            Encoding::OneHot => {
+                let hot_idx = match self {
+                    Position::Diving(depth) => *depth,
+                    Position::WaitingToDive => config.path_length,
+                    Position::ReturnedToSubmarine => config.path_length + 1,
+                };
+                UnifiedIterator::Opt1(one_hot(hot_idx, self.unpacked_size_with(config)))
+            }
+            _ => UnifiedIterator::Opt2(
+                [
+                    depth_val,
+                    T::from(*self == Position::WaitingToDive),
+                    T::from(*self == Position::ReturnedToSubmarine),
+                ]
+                .into_iter(),
+            ),
+        }
     }
 
-    fn unpacked_size(&self) -> usize {
-        POSITION_COUNT
+    fn unpacked_size_with(&self, config: EncoderConfig) -> usize {
+        match config.position {
+            // A slot per depth, then waiting and returned.
+            Encoding::OneHot => config.path_length + 2,
+            _ => POSITION_COUNT,
+        }
     }
 }
 
 impl Unpackable for Tile {
-    fn unpack<T: DataType>(&self) -> impl Iterator<Item = T> {
-        match self {
-            Tile::Empty => UnifiedIterator::Opt1([T::zero()].into_iter()),
-            Tile::Treasure(treasure) => UnifiedIterator::Opt2(treasure.unpack::<T>()),
+    fn unpack_with<T: DataType>(&self, config: EncoderConfig) -> impl Iterator<Item = T> {
+        // Empty is 0, treasures are 1-indexed by level.
+        let value = match self {
+            Tile::Empty => 0,
+            Tile::Treasure(treasure) => 1 + *treasure as usize,
+        };
+        match config.tile {
+            Encoding::Raw => UnifiedIterator::Opt1(std::iter::once(T::from(value as u16))),
+            Encoding::Normalized => {
+                UnifiedIterator::Opt1(std::iter::once(normalized(value, Treasure::COUNT)))
+            }
+            Encoding::OneHot => UnifiedIterator::Opt2(one_hot(value, 1 + Treasure::COUNT)),
         }
     }
 
-    fn unpacked_size(&self) -> usize {
-        1
+    fn unpacked_size_with(&self, config: EncoderConfig) -> usize {
+        config.tile.size(1 + Treasure::COUNT)
+    }
+}
+
+impl Path {
+    /// Features per tile: the tile, whether it is occupied and, if padded, whether it exists.
+    fn tile_size(config: EncoderConfig) -> usize {
+        config.tile.size(1 + Treasure::COUNT) + 1 + usize::from(config.padded)
     }
 }
 
 impl Unpackable for Path {
-    fn unpack<T: DataType>(&self) -> impl Iterator<Item = T> {
-        let path_iter = self.tiles.unpack::<T>();
-        let occupied_iter = (0..self.tiles.len()).map(|idx| T::from(self.occupied.contains(idx)));
-        itertools::interleave(path_iter, occupied_iter)
+    fn unpack_with<T: DataType>(&self, config: EncoderConfig) -> impl Iterator<Item = T> {
+        debug_assert!(!config.padded || self.tiles.len() <= config.path_length);
+        let tiles = self.tiles.iter().enumerate().flat_map(move |(idx, tile)| {
+            let present = config.padded.then(|| T::one());
+            tile.unpack_with::<T>(config)
+                .chain(std::iter::once(T::from(self.occupied.contains(idx))))
+                .chain(present)
+        });
+        let padding = self.unpacked_size_with(config) - self.tiles.len() * Path::tile_size(config);
+        tiles.chain(std::iter::repeat_n(T::zero(), padding))
+    }
+
+    fn unpack_into<T: DataType>(&self, config: EncoderConfig, out: &mut [T]) {
+        assert_eq!(out.len(), self.unpacked_size_with(config));
+        let tile_size = Path::tile_size(config);
+        let tile_width = config.tile.size(1 + Treasure::COUNT);
+        for (idx, slot) in out.chunks_exact_mut(tile_size).enumerate() {
+            let Some(tile) = self.tiles.get(idx) else {
+                slot.fill(T::zero());
+                continue;
+            };
+            tile.unpack_into(config, &mut slot[..tile_width]);
+            slot[tile_width] = T::from(self.occupied.contains(idx));
+            if config.padded {
+                slot[tile_width + 1] = T::one();
+            }
+        }
     }
 
-    fn unpacked_size(&self) -> usize {
-        2 * self.tiles.len()
+    fn unpacked_size_with(&self, config: EncoderConfig) -> usize {
+        let num_tiles = if config.padded {
+            config.path_length
+        } else {
+            self.tiles.len()
+        };
+        num_tiles * Path::tile_size(config)
+    }
+}
+
+impl Unpackable for Turn {
+    /// Whether the turn was taken, whether it went up and whether the player ignored or took a
+    /// treasure, followed by the treasure they returned if any.
+    fn unpack_with<T: DataType>(&self, config: EncoderConfig) -> impl Iterator<Item = T> {
+        let flags = [
+            T::one(),
+            T::from(self.direction == DiveDirection::Up),
+            T::from(self.treasure == Some(TreasureDecision::Ignore)),
+            T::from(self.treasure == Some(TreasureDecision::Take)),
+        ];
+        let returned = match &self.treasure {
+            Some(TreasureDecision::Return(treasure)) => {
+                UnifiedIterator::Opt1(treasure.unpack_with::<T>(config))
+            }
+            _ => UnifiedIterator::Opt2(std::iter::repeat_n(
+                T::zero(),
+                config.treasure.size(Treasure::COUNT),
+            )),
+        };
+        flags.into_iter().chain(returned)
+    }
+
+    fn unpacked_size_with(&self, config: EncoderConfig) -> usize {
+        History::turn_size(config)
+    }
+}
+
+impl History {
+    fn turn_size(config: EncoderConfig) -> usize {
+        4 + config.treasure.size(Treasure::COUNT)
+    }
+}
+
+impl Unpackable for History {
+    /// The latest `config.history_turns` turns, zero-padded, then whether the player turned
+    /// around, after how many turns, and how many treasures they took and returned.
+    fn unpack_with<T: DataType>(&self, config: EncoderConfig) -> impl Iterator<Item = T> {
+        let num_turns = config.history_turns.unwrap_or_default();
+        let turns = self.recent_turns.iter().take(num_turns);
+        let padding = (num_turns - turns.len()) * History::turn_size(config);
+        let count = |value: usize| match config.count_encoding() {
+            Encoding::Normalized => normalized(value.min(config.path_length), config.path_length),
+            _ => T::from(value as u16),
+        };
+        let summary = [
+            T::from(self.turned_around.is_some()),
+            count(self.turned_around.unwrap_or_default()),
+            count(self.treasures_taken),
+            count(self.treasures_returned),
+        ];
+        turns
+            .flat_map(move |turn| turn.unpack_with::<T>(config))
+            .chain(std::iter::repeat_n(T::zero(), padding))
+            .chain(summary)
+    }
+
+    fn unpacked_size_with(&self, config: EncoderConfig) -> usize {
+        config.history_turns.unwrap_or_default() * History::turn_size(config) + 4
     }
 }
 
 impl Unpackable for Player {
-    fn unpack<T: DataType>(&self) -> impl Iterator<Item = T> {
-        let direction = self.direction.unpack::<T>();
-        let position = self.position.unpack::<T>();
-        let held_treasures = self.held_treasures.unpack::<T>();
-        let filled_size = self.direction.unpacked_size()
-            + self.position.unpacked_size()
-            + self.held_treasures.unpacked_size();
+    fn unpack_with<T: DataType>(&self, config: EncoderConfig) -> impl Iterator<Item = T> {
+        let direction = self.direction.unpack_with::<T>(config);
+        let position = self.position.unpack_with::<T>(config);
+        let held_treasures = self.held_treasures.unpack_with::<T>(config);
+        let treasure_padding = (MAX_NUM_TREASURES * config.treasure.size(Treasure::COUNT))
+            .saturating_sub(self.held_treasures.unpacked_size_with(config));
+        let history = config
+            .history_turns
+            .map(|_| self.history.unpack_with::<T>(config))
+            .into_iter()
+            .flatten();
         direction
             .chain(position)
             .chain(held_treasures)
-            .chain(std::iter::repeat_n(
-                T::zero(),
-                self.unpacked_size().saturating_sub(filled_size),
-            ))
+            .chain(std::iter::repeat_n(T::zero(), treasure_padding))
+            .chain(history)
     }
 
-    fn unpacked_size(&self) -> usize {
-        self.direction.unpacked_size() + self.position.unpacked_size() + MAX_NUM_TREASURES
+    fn unpacked_size_with(&self, config: EncoderConfig) -> usize {
+        let history_size = match config.history_turns {
+            Some(_) => self.history.unpacked_size_with(config),
+            None => 0,
+        };
+        self.direction.unpacked_size_with(config)
+            + self.position.unpacked_size_with(config)
+            + MAX_NUM_TREASURES * config.treasure.size(Treasure::COUNT)
+            + history_size
     }
 }
 
 impl Unpackable for DeepSeaAction {
-    fn unpack<T: DataType>(&self) -> impl Iterator<Item = T> {
-        // let mut unpacked = vec![T::zero(); DEEP_SEA_ACTION_ENUM_COUNT];
-        let hot_idx = match self {
-            DeepSeaAction::TreasureDecision(td) => match td {
-                TreasureDecision::Ignore => 0,
-                TreasureDecision::Take => 1,
-                TreasureDecision::Return(t) => 2 + *t as usize,
-            },
-            DeepSeaAction::DiveDirection(dd) => TREASURE_DECISION_COUNT + *dd as usize,
+    fn unpack_with<T: DataType>(&self, _config: EncoderConfig) -> impl Iterator<Item = T> {
+        one_hot(self.index(), DEEP_SEA_ACTION_COUNT)
+    }
+
+    fn unpacked_size_with(&self, _config: EncoderConfig) -> usize {
+        DEEP_SEA_ACTION_COUNT
+    }
+}
+
+impl DeepSeaState {
+    fn player_slots(&self, config: EncoderConfig) -> usize {
+        if config.padded {
+            config.max_players
+        } else {
+            self.players.len()
+        }
+    }
+
+    /// Features per player slot, including its presence flag if padded.
+    fn player_size(config: EncoderConfig) -> usize {
+        Player::new().unpacked_size_with(config) + usize::from(config.padded)
+    }
+
+    /// The oxygen left followed by the acting player, which come after the players.
+    fn trailing_features<T: DataType>(&self, config: EncoderConfig) -> impl Iterator<Item = T> {
+        let oxygen = self.oxygen as usize;
+        let max_oxygen = DeepSea::OXYGEN as usize;
+        let oxygen_iter = match config.oxygen {
+            Encoding::Raw => UnifiedIterator::Opt1(std::iter::once(T::from(self.oxygen))),
+            Encoding::Normalized => {
+                UnifiedIterator::Opt1(std::iter::once(normalized(oxygen, max_oxygen)))
+            }
+            Encoding::OneHot => UnifiedIterator::Opt2(one_hot(oxygen, max_oxygen + 1)),
         };
-        std::iter::repeat_n(T::zero(), hot_idx)
-            .chain(std::iter::once(T::from(1u16)))
-            .chain(std::iter::repeat_n(
-                T::zero(),
-                self.unpacked_size() - hot_idx - 1,
-            ))
+        oxygen_iter.chain((0..self.player_slots(config)).map(|idx| T::from(idx == self.player_idx)))
     }
+}
 
-    fn unpacked_size(&self) -> usize {
+impl Unpackable for ActionMask {
+    fn unpack_with<T: DataType>(&self, _config: EncoderConfig) -> impl Iterator<Item = T> {
+        self.0.into_iter().map(T::from)
+    }
+
+    fn unpacked_size_with(&self, _config: EncoderConfig) -> usize {
         DEEP_SEA_ACTION_COUNT
     }
 }
 
 impl Unpackable for DeepSeaState {
-    fn unpack<T: DataType>(&self) -> impl Iterator<Item = T> {
+    fn unpack_with<T: DataType>(&self, config: EncoderConfig) -> impl Iterator<Item = T> {
+        debug_assert!(!config.padded || self.players.len() <= config.max_players);
+        let players = self.players.iter().flat_map(move |player| {
+            let present = config.padded.then(|| T::one());
+            player.unpack_with::<T>(config).chain(present)
+        });
+        let player_padding =
+            (self.player_slots(config) - self.players.len()) * DeepSeaState::player_size(config);
         self.path
-            .unpack::<T>()
-            .collect_vec()
-            .into_iter()
-            .chain(self.players.unpack())
-            .chain([T::from(self.oxygen)])
+            .unpack_with::<T>(config)
+            .chain(players)
+            .chain(std::iter::repeat_n(T::zero(), player_padding))
+            .chain(self.trailing_features(config))
     }
 
-    fn unpacked_size(&self) -> usize {
-        let path_dim = self.path.unpacked_size();
-        let players_dim = self.players.unpacked_size();
-        let oxygen_dim = 1;
-        path_dim + players_dim + oxygen_dim
+    fn unpack_into<T: DataType>(&self, config: EncoderConfig, out: &mut [T]) {
+        assert_eq!(
+            out.len(),
+            self.unpacked_size_with(config),
+            "Buffer does not fit the unpacked features"
+        );
+        let (path, rest) = out.split_at_mut(self.path.unpacked_size_with(config));
+        self.path.unpack_into(config, path);
+
+        let player_size = DeepSeaState::player_size(config);
+        let (players, rest) = rest.split_at_mut(self.player_slots(config) * player_size);
+        let player_width = player_size - usize::from(config.padded);
+        for (idx, slot) in players.chunks_exact_mut(player_size).enumerate() {
+            let Some(player) = self.players.get(idx) else {
+                slot.fill(T::zero());
+                continue;
+            };
+            player.unpack_into(config, &mut slot[..player_width]);
+            if config.padded {
+                slot[player_width] = T::one();
+            }
+        }
+
+        for (slot, value) in rest.iter_mut().zip(self.trailing_features(config)) {
+            *slot = value;
+        }
+    }
+
+    fn unpacked_size_with(&self, config: EncoderConfig) -> usize {
+        let path_dim = self.path.unpacked_size_with(config);
+        let players_dim = self.player_slots(config) * DeepSeaState::player_size(config);
+        let oxygen_dim = config.oxygen.size(DeepSea::OXYGEN as usize + 1);
+        let player_idx_dim = self.player_slots(config);
+        path_dim + players_dim + oxygen_dim + player_idx_dim
     }
 }
 
 impl<'a> Unpackable for DeepSeaStateActionPair<'a> {
-    fn unpack<T: DataType>(&self) -> impl Iterator<Item = T> {
-        self.state.unpack::<T>().chain(self.action.unpack::<T>())
+    fn unpack_with<T: DataType>(&self, config: EncoderConfig) -> impl Iterator<Item = T> {
+        self.state
+            .unpack_with::<T>(config)
+            .chain(self.action.unpack_with::<T>(config))
+    }
+
+    fn unpack_into<T: DataType>(&self, config: EncoderConfig, out: &mut [T]) {
+        let (state, action) = out.split_at_mut(self.state.unpacked_size_with(config));
+        self.state.unpack_into(config, state);
+        self.action.unpack_into(config, action);
+    }
+
+    fn unpacked_size_with(&self, config: EncoderConfig) -> usize {
+        self.state.unpacked_size_with(config) + self.action.unpacked_size_with(config)
+    }
+}
+
+/// Features of a value encoded with `encoding`, naming one-hot slots by `value_names`.
+fn describe_encoded(
+    prefix: &str,
+    encoding: Encoding,
+    value_names: impl IntoIterator<Item = String>,
+    features: &mut Vec<Feature>,
+) {
+    match encoding {
+        Encoding::OneHot => features.extend(
+            value_names
+                .into_iter()
+                .map(|value| Feature::new(format!("{prefix}={value}"), FeatureKind::Flag)),
+        ),
+        _ => features.push(Feature::new(prefix, encoding.into())),
+    }
+}
+
+impl Describable for Treasure {
+    fn describe_into(&self, prefix: &str, config: EncoderConfig, features: &mut Vec<Feature>) {
+        let names = TREASURES.iter().map(|t| format!("{t:?}"));
+        describe_encoded(prefix, config.treasure, names, features);
+    }
+}
+
+impl Describable for DiveDirection {
+    fn describe_into(&self, prefix: &str, _config: EncoderConfig, features: &mut Vec<Feature>) {
+        features.push(Feature::new(format!("{prefix}=Up"), FeatureKind::Flag));
+    }
+}
+
+impl Describable for Position {
+    fn describe_into(&self, prefix: &str, config: EncoderConfig, features: &mut Vec<Feature>) {
+        if config.position == Encoding::OneHot {
+            let names = (0..config.path_length).map(|depth| format!("Diving({depth})"));
+            describe_encoded(prefix, config.position, names, features);
+        } else {
+            features.push(Feature::new(
+                field_name(prefix, "depth"),
+                config.position.into(),
+            ));
+        }
+        for position in [Position::WaitingToDive, Position::ReturnedToSubmarine] {
+            features.push(Feature::new(
+                format!("{prefix}={position:?}"),
+                FeatureKind::Flag,
+            ));
+        }
+    }
+}
+
+impl Describable for Tile {
+    fn describe_into(&self, prefix: &str, config: EncoderConfig, features: &mut Vec<Feature>) {
+        let names =
+            std::iter::once("Empty".to_owned()).chain(TREASURES.iter().map(|t| format!("{t:?}")));
+        describe_encoded(prefix, config.tile, names, features);
+    }
+}
+
+impl Describable for Path {
+    fn describe_into(&self, prefix: &str, config: EncoderConfig, features: &mut Vec<Feature>) {
+        let num_tiles = self.unpacked_size_with(config) / Path::tile_size(config);
+        for idx in 0..num_tiles {
+            let tile = field_name(prefix, &format!("tiles[{idx}]"));
+            Tile::Empty.describe_into(&tile, config, features);
+            features.push(Feature::new(
+                field_name(prefix, &format!("occupied[{idx}]")),
+                FeatureKind::Flag,
+            ));
+            if config.padded {
+                features.push(Feature::new(
+                    field_name(prefix, &format!("present[{idx}]")),
+                    FeatureKind::Flag,
+                ));
+            }
+        }
     }
+}
 
-    fn unpacked_size(&self) -> usize {
-        self.state.unpacked_size() + self.action.unpacked_size()
+impl Describable for Player {
+    fn describe_into(&self, prefix: &str, config: EncoderConfig, features: &mut Vec<Feature>) {
+        self.direction
+            .describe_into(&field_name(prefix, "direction"), config, features);
+        self.position
+            .describe_into(&field_name(prefix, "position"), config, features);
+        // Every slot is named, held or not.
+        for idx in 0..MAX_NUM_TREASURES {
+            let slot = field_name(prefix, &format!("held_treasures[{idx}]"));
+            Treasure::One.describe_into(&slot, config, features);
+        }
+        if config.history_turns.is_some() {
+            self.history
+                .describe_into(&field_name(prefix, "history"), config, features);
+        }
+    }
+}
+
+impl Describable for Turn {
+    fn describe_into(&self, prefix: &str, config: EncoderConfig, features: &mut Vec<Feature>) {
+        let flag = |name: &str| Feature::new(field_name(prefix, name), FeatureKind::Flag);
+        features.extend([
+            flag("taken"),
+            flag("direction=Up"),
+            flag("treasure=Ignore"),
+            flag("treasure=Take"),
+        ]);
+        Treasure::One.describe_into(&field_name(prefix, "returned"), config, features);
+    }
+}
+
+impl Describable for History {
+    fn describe_into(&self, prefix: &str, config: EncoderConfig, features: &mut Vec<Feature>) {
+        let turn = Turn {
+            direction: DiveDirection::Down,
+            treasure: None,
+        };
+        for idx in 0..config.history_turns.unwrap_or_default() {
+            let slot = field_name(prefix, &format!("recent_turns[{idx}]"));
+            turn.describe_into(&slot, config, features);
+        }
+        let count_kind = config.count_encoding().into();
+        features.extend([
+            Feature::new(field_name(prefix, "turned_around"), FeatureKind::Flag),
+            Feature::new(field_name(prefix, "turned_around.turns"), count_kind),
+            Feature::new(field_name(prefix, "treasures_taken"), count_kind),
+            Feature::new(field_name(prefix, "treasures_returned"), count_kind),
+        ]);
+    }
+}
+
+impl Describable for DeepSeaAction {
+    fn describe_into(&self, prefix: &str, _config: EncoderConfig, features: &mut Vec<Feature>) {
+        for idx in 0..DEEP_SEA_ACTION_COUNT {
+            let action = match DeepSeaAction::from_index(idx).unwrap() {
+                DeepSeaAction::TreasureDecision(td) => format!("{td:?}"),
+                DeepSeaAction::DiveDirection(dd) => format!("{dd:?}"),
+            };
+            features.push(Feature::new(
+                format!("{prefix}={action}"),
+                FeatureKind::Flag,
+            ));
+        }
+    }
+}
+
+impl Describable for ActionMask {
+    fn describe_into(&self, prefix: &str, config: EncoderConfig, features: &mut Vec<Feature>) {
+        DeepSeaAction::from_index(0)
+            .unwrap()
+            .describe_into(prefix, config, features);
+    }
+}
+
+impl Describable for DeepSeaState {
+    fn describe_into(&self, prefix: &str, config: EncoderConfig, features: &mut Vec<Feature>) {
+        self.path
+            .describe_into(&field_name(prefix, "path"), config, features);
+        for idx in 0..self.player_slots(config) {
+            let slot = field_name(prefix, &format!("players[{idx}]"));
+            Player::new().describe_into(&slot, config, features);
+            if config.padded {
+                features.push(Feature::new(
+                    field_name(&slot, "present"),
+                    FeatureKind::Flag,
+                ));
+            }
+        }
+        let oxygen_names = (0..=DeepSea::OXYGEN).map(|oxygen| oxygen.to_string());
+        describe_encoded(
+            &field_name(prefix, "oxygen"),
+            config.oxygen,
+            oxygen_names,
+            features,
+        );
+        for idx in 0..self.player_slots(config) {
+            features.push(Feature::new(
+                format!("{}={idx}", field_name(prefix, "player_idx")),
+                FeatureKind::Flag,
+            ));
+        }
+    }
+}
+
+impl<'a> Describable for DeepSeaStateActionPair<'a> {
+    fn describe_into(&self, prefix: &str, config: EncoderConfig, features: &mut Vec<Feature>) {
+        self.state.describe_into(prefix, config, features);
+        self.action
+            .describe_into(&field_name(prefix, "action"), config, features);
+    }
+}
+
+/// Number of players and tiles of a packed `DeepSeaState`, which its features alone do not
+/// determine unless they are padded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct StateShape {
+    pub path_length: usize,
+    pub num_players: usize,
+}
+
+fn decode_error<T: DataType>(what: &str, values: &[T]) -> DeepSeaError {
+    DeepSeaError::CorruptReplay(format!("Cannot decode {what} from {values:?}"))
+}
+
+fn decode_flag<T: DataType>(value: &T) -> bool {
+    value.to_f32() > 0.5
+}
+
+/// Reads a raw or normalized value, where a normalized 1 stands for `max`.
+fn decode_scalar<T: DataType>(
+    values: &mut &[T],
+    encoding: Encoding,
+    max: usize,
+) -> DeepSeaResult<usize> {
+    debug_assert_ne!(encoding, Encoding::OneHot);
+    let value = take_values(values, 1)?;
+    let scaled = match encoding {
+        Encoding::Normalized => value[0].to_f32() * max.max(1) as f32,
+        _ => value[0].to_f32(),
+    };
+    if scaled < -0.5 {
+        return Err(decode_error("a non-negative value", value));
+    }
+    Ok(scaled.round() as usize)
+}
+
+/// Reads `size` one-hot slots, giving the hot one or `None` if all are cold.
+fn decode_one_hot<T: DataType>(values: &mut &[T], size: usize) -> DeepSeaResult<Option<usize>> {
+    let slots = take_values(values, size)?;
+    let hot = slots
+        .iter()
+        .map(|x| x.to_f32())
+        .position_max_by(f32::total_cmp);
+    Ok(hot.filter(|&idx| decode_flag(&slots[idx])))
+}
+
+/// Reads a 1-indexed treasure, where 0 stands for no treasure.
+fn decode_treasure<T: DataType>(
+    values: &mut &[T],
+    encoding: Encoding,
+) -> DeepSeaResult<Option<Treasure>> {
+    let value = match encoding {
+        Encoding::OneHot => decode_one_hot(values, Treasure::COUNT)?.map(|idx| idx + 1),
+        _ => Some(decode_scalar(values, encoding, Treasure::COUNT)?).filter(|&v| v != 0),
+    };
+    value
+        .map(|v| {
+            TREASURES
+                .get(v - 1)
+                .copied()
+                .ok_or_else(|| DeepSeaError::CorruptReplay(format!("No treasure with value {v}")))
+        })
+        .transpose()
+}
+
+impl Packable for Treasure {
+    type Shape = ();
+
+    fn read_from<T: DataType>(
+        values: &mut &[T],
+        _shape: (),
+        config: EncoderConfig,
+    ) -> DeepSeaResult<Self> {
+        let start = *values;
+        decode_treasure(values, config.treasure)?
+            .ok_or_else(|| decode_error("a treasure", &start[..start.len() - values.len()]))
+    }
+}
+
+impl Packable for DiveDirection {
+    type Shape = ();
+
+    fn read_from<T: DataType>(
+        values: &mut &[T],
+        _shape: (),
+        _config: EncoderConfig,
+    ) -> DeepSeaResult<Self> {
+        Ok(if decode_flag(&take_values(values, 1)?[0]) {
+            DiveDirection::Up
+        } else {
+            DiveDirection::Down
+        })
+    }
+}
+
+impl Packable for Position {
+    type Shape = ();
+
+    fn read_from<T: DataType>(
+        values: &mut &[T],
+        _shape: (),
+        config: EncoderConfig,
+    ) -> DeepSeaResult<Self> {
+        let path_length = config.path_length;
+        if config.position == Encoding::OneHot {
+            let start = *values;
+            return match decode_one_hot(values, path_length + 2)? {
+                Some(depth) if depth < path_length => Ok(Position::Diving(depth)),
+                Some(idx) if idx == path_length => Ok(Position::WaitingToDive),
+                Some(_) => Ok(Position::ReturnedToSubmarine),
+                None => Err(decode_error("a position", &start[..path_length + 2])),
+            };
+        }
+        let depth = decode_scalar(values, config.position, path_length - 1)?;
+        let flags = take_values(values, 2)?;
+        Ok(if decode_flag(&flags[1]) {
+            Position::ReturnedToSubmarine
+        } else if decode_flag(&flags[0]) {
+            Position::WaitingToDive
+        } else {
+            Position::Diving(depth)
+        })
+    }
+}
+
+impl Packable for Tile {
+    type Shape = ();
+
+    fn read_from<T: DataType>(
+        values: &mut &[T],
+        _shape: (),
+        config: EncoderConfig,
+    ) -> DeepSeaResult<Self> {
+        let start = *values;
+        let value = match config.tile {
+            Encoding::OneHot => decode_one_hot(values, 1 + Treasure::COUNT)?,
+            _ => Some(decode_scalar(values, config.tile, Treasure::COUNT)?),
+        };
+        match value {
+            Some(0) => Ok(Tile::Empty),
+            Some(v) if v <= Treasure::COUNT => Ok(Tile::Treasure(TREASURES[v - 1])),
+            _ => Err(decode_error("a tile", &start[..start.len() - values.len()])),
+        }
+    }
+}
+
+impl Packable for Path {
+    /// Number of tiles, unused if padded since absent tiles are flagged.
+    type Shape = usize;
+
+    fn read_from<T: DataType>(
+        values: &mut &[T],
+        path_length: usize,
+        config: EncoderConfig,
+    ) -> DeepSeaResult<Self> {
+        let num_slots = if config.padded {
+            config.path_length
+        } else {
+            path_length
+        };
+        let mut path = Path {
+            tiles: Vec::with_capacity(num_slots),
+            occupied: BitSet::new(),
+        };
+        for idx in 0..num_slots {
+            // Absent tiles are all zero, which need not decode as a tile.
+            let slot = take_values(values, Path::tile_size(config))?;
+            if config.padded && !decode_flag(slot.last().unwrap()) {
+                continue;
+            }
+            let mut slot = slot;
+            path.tiles.push(Tile::read_from(&mut slot, (), config)?);
+            if decode_flag(&slot[0]) {
+                path.occupied.insert(idx);
+            }
+        }
+        Ok(path)
+    }
+}
+
+impl Packable for Player {
+    type Shape = ();
+
+    fn read_from<T: DataType>(
+        values: &mut &[T],
+        _shape: (),
+        config: EncoderConfig,
+    ) -> DeepSeaResult<Self> {
+        let direction = DiveDirection::read_from(values, (), config)?;
+        let position = Position::read_from(values, (), config)?;
+        let mut held_treasures = vec![];
+        for _ in 0..MAX_NUM_TREASURES {
+            held_treasures.extend(decode_treasure(values, config.treasure)?);
+        }
+        let history = match config.history_turns {
+            Some(_) => History::read_from(values, (), config)?,
+            None => History::default(),
+        };
+        Ok(Player {
+            direction,
+            position,
+            held_treasures,
+            history,
+        })
+    }
+}
+
+impl Packable for Turn {
+    type Shape = ();
+
+    fn read_from<T: DataType>(
+        values: &mut &[T],
+        _shape: (),
+        config: EncoderConfig,
+    ) -> DeepSeaResult<Self> {
+        let flags = take_values(values, 4)?;
+        let returned = decode_treasure(values, config.treasure)?;
+        let treasure = if let Some(treasure) = returned {
+            Some(TreasureDecision::Return(treasure))
+        } else if decode_flag(&flags[3]) {
+            Some(TreasureDecision::Take)
+        } else if decode_flag(&flags[2]) {
+            Some(TreasureDecision::Ignore)
+        } else {
+            None
+        };
+        let direction = if decode_flag(&flags[1]) {
+            DiveDirection::Up
+        } else {
+            DiveDirection::Down
+        };
+        Ok(Turn {
+            direction,
+            treasure,
+        })
+    }
+}
+
+impl Packable for History {
+    type Shape = ();
+
+    /// Recovers only the turns `config` encodes.
+    fn read_from<T: DataType>(
+        values: &mut &[T],
+        _shape: (),
+        config: EncoderConfig,
+    ) -> DeepSeaResult<Self> {
+        let mut recent_turns = vec![];
+        for _ in 0..config.history_turns.unwrap_or_default() {
+            let mut slot = take_values(values, History::turn_size(config))?;
+            // Turns not yet taken are all zero.
+            if decode_flag(&slot[0]) {
+                recent_turns.push(Turn::read_from(&mut slot, (), config)?);
+            }
+        }
+        let turned_around = decode_flag(&take_values(values, 1)?[0]);
+        let mut count = || decode_scalar(values, config.count_encoding(), config.path_length);
+        let turns_before_turning = count()?;
+        Ok(History {
+            recent_turns,
+            turned_around: turned_around.then_some(turns_before_turning),
+            treasures_taken: count()?,
+            treasures_returned: count()?,
+        })
+    }
+}
+
+impl Packable for DeepSeaAction {
+    type Shape = ();
+
+    /// Decodes the highest of the action slots, so that a model's scores over
+    /// `DEEP_SEA_ACTION_COUNT` actions map back to its favourite.
+    fn read_from<T: DataType>(
+        values: &mut &[T],
+        _shape: (),
+        _config: EncoderConfig,
+    ) -> DeepSeaResult<Self> {
+        let slots = take_values(values, DEEP_SEA_ACTION_COUNT)?;
+        let index = slots
+            .iter()
+            .map(|x| x.to_f32())
+            .position_max_by(f32::total_cmp)
+            .unwrap();
+        Ok(DeepSeaAction::from_index(index).unwrap())
+    }
+}
+
+impl Packable for DeepSeaState {
+    type Shape = StateShape;
+
+    fn read_from<T: DataType>(
+        values: &mut &[T],
+        shape: StateShape,
+        config: EncoderConfig,
+    ) -> DeepSeaResult<Self> {
+        let path = Path::read_from(values, shape.path_length, config)?;
+        let num_slots = if config.padded {
+            config.max_players
+        } else {
+            shape.num_players
+        };
+        let mut players = Vec::with_capacity(num_slots);
+        for _ in 0..num_slots {
+            let mut slot = take_values(values, DeepSeaState::player_size(config))?;
+            if !config.padded || decode_flag(slot.last().unwrap()) {
+                players.push(Player::read_from(&mut slot, (), config)?);
+            }
+        }
+        let max_oxygen = DeepSea::OXYGEN as usize;
+        let start = *values;
+        let oxygen = match config.oxygen {
+            Encoding::OneHot => decode_one_hot(values, max_oxygen + 1)?
+                .ok_or_else(|| decode_error("oxygen", &start[..max_oxygen + 1]))?,
+            _ => decode_scalar(values, config.oxygen, max_oxygen)?,
+        };
+        let start = *values;
+        let player_idx = decode_one_hot(values, num_slots)?
+            .ok_or_else(|| decode_error("the acting player", &start[..num_slots]))?;
+        Ok(DeepSeaState {
+            path,
+            players,
+            oxygen: oxygen as u16,
+            player_idx,
+        })
+    }
+}
+
+impl DeepSeaState {
+    /// Drops the history `config` does not encode, leaving what packing its features recovers.
+    pub fn forget_history(&mut self, config: EncoderConfig) {
+        for player in &mut self.players {
+            match config.history_turns {
+                Some(turns) => player.history.recent_turns.truncate(turns),
+                None => player.history = History::default(),
+            }
+        }
+    }
+
+    pub fn shape(&self) -> StateShape {
+        StateShape {
+            path_length: self.path.tiles.len(),
+            num_players: self.players.len(),
+        }
     }
 }
 
@@ -244,14 +1258,18 @@ mod tests {
     use crate::{
         deep_sea::{DeepSea, DiveDirection, Position, Tile},
         deep_sea_vectorization::{
-            DEEP_SEA_ACTION_COUNT, DeepSeaAction, DeepSeaState, DeepSeaStateActionPair, Path,
-            Player,
+            ActionMask, DEEP_SEA_ACTION_COUNT, DEFAULT_PATH_LENGTH, DeepSeaAction, DeepSeaState,
+            DeepSeaStateActionPair, EncoderConfig, History, Path, Perspective, Player, StateShape,
         },
+        engine::Engine,
+        error::DeepSeaResult,
+        ml::env::DeepSeaEnv,
         ml::vectorization::*,
         solver::TreasureDecision,
         treasure::Treasure,
     };
     use bit_set::BitSet;
+    use rand::{SeedableRng, rngs::StdRng, seq::IndexedRandom};
 
     #[test]
     fn test_tiles_vectorization() {
@@ -318,9 +1336,8 @@ mod tests {
 
     #[test]
     fn test_player_vectorization() {
-        // A hack to get Player since ::new() is private.
         let deep_sea = DeepSea::new(vec![], 1);
-        let player = Player::from(deep_sea.players()[0].clone());
+        let player = Player::from(&deep_sea.players()[0]);
         let player_size = &[player.unpacked_size()];
         let player_i32_ndarray: ndarray::Array1<i32> = player.clone().into_ndarray();
         assert_eq!(player_i32_ndarray.shape(), player_size);
@@ -332,12 +1349,14 @@ mod tests {
             Player {
                 direction: DiveDirection::Up,
                 position: Position::Diving(5),
-                held_treasures: vec![Treasure::One, Treasure::Four]
+                held_treasures: vec![Treasure::One, Treasure::Four],
+                history: History::default(),
             },
             Player {
                 direction: DiveDirection::Down,
                 position: Position::ReturnedToSubmarine,
                 held_treasures: vec![Treasure::Three],
+                history: History::default(),
             }
         ]);
         let players_size = &[players.unpacked_size()];
@@ -407,4 +1426,539 @@ mod tests {
         let state_action_f32_tensor = state_action.into_tensordata::<f32>();
         assert_eq!(state_action_f32_tensor.shape, state_action_size);
     }
+
+    #[test]
+    fn test_state_from_deep_sea() -> DeepSeaResult {
+        let mut deep_sea = DeepSea::new(vec![Tile::Treasure(Treasure::Two), Tile::Empty], 3);
+        deep_sea.move_player(DiveDirection::Down, 1)?;
+        deep_sea.take_treasure(TreasureDecision::Take)?;
+        deep_sea.next_player();
+        deep_sea.take_oxygen();
+
+        let state = DeepSeaState::from(&deep_sea);
+        assert_eq!(state.path.tiles, vec![Tile::Empty, Tile::Empty]);
+        assert!(state.path.occupied.contains(0));
+        assert!(!state.path.occupied.contains(1));
+        assert_eq!(state.players.len(), 3);
+        assert_eq!(state.players[0].position, Position::Diving(0));
+        assert_eq!(state.players[0].held_treasures, vec![Treasure::Two]);
+        assert_eq!(state.players[1].position, Position::WaitingToDive);
+        assert_eq!(state.oxygen, DeepSea::OXYGEN as u16);
+        assert_eq!(state.player_idx, 1);
+
+        // The acting player is one-hot encoded at the end.
+        let unpacked = state.into_ndarray::<f32>();
+        let len = unpacked.len();
+        assert_eq!(
+            unpacked.iter().skip(len - 3).cloned().collect::<Vec<_>>(),
+            vec![0., 1., 0.]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_action_index_round_trip() {
+        for idx in 0..DEEP_SEA_ACTION_COUNT {
+            assert_eq!(DeepSeaAction::from_index(idx).unwrap().index(), idx);
+        }
+        assert_eq!(DeepSeaAction::from_index(DEEP_SEA_ACTION_COUNT), None);
+    }
+
+    #[test]
+    fn test_perspective() -> DeepSeaResult {
+        let mut deep_sea = DeepSea::new((0..4).map(|_| Tile::Empty).collect(), 3);
+        deep_sea.move_player(DiveDirection::Down, 1)?;
+        deep_sea.next_player();
+        deep_sea.next_player();
+        deep_sea.move_player(DiveDirection::Down, 3)?;
+
+        let perspective = Perspective::acting(&deep_sea);
+        assert_eq!(perspective.seat(), 2);
+        assert_eq!(perspective.to_relative(2), 0);
+        assert_eq!(perspective.to_relative(0), 1);
+        assert_eq!(perspective.to_relative(1), 2);
+        for relative in 0..3 {
+            assert_eq!(
+                perspective.to_relative(perspective.to_absolute(relative)),
+                relative
+            );
+        }
+
+        let state = perspective.observe(&deep_sea);
+        assert_eq!(state.player_idx, 0);
+        assert_eq!(state.players[0].position, Position::Diving(3));
+        assert_eq!(state.players[1].position, Position::Diving(0));
+        assert_eq!(state.players[2].position, Position::WaitingToDive);
+        assert_eq!(
+            perspective.decode_action(7),
+            Some(DeepSeaAction::DiveDirection(DiveDirection::Up))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_one_hot_layout() {
+        let config = EncoderConfig::uniform(Encoding::OneHot);
+        let collect = |iter: &mut dyn Iterator<Item = f32>| iter.collect::<Vec<_>>();
+
+        assert_eq!(
+            collect(&mut Treasure::Three.unpack_with(config)),
+            vec![0., 0., 1., 0.]
+        );
+        assert_eq!(
+            collect(&mut Tile::Empty.unpack_with(config)),
+            vec![1., 0., 0., 0., 0.]
+        );
+        assert_eq!(
+            collect(&mut Tile::Treasure(Treasure::One).unpack_with(config)),
+            vec![0., 1., 0., 0., 0.]
+        );
+
+        let config = EncoderConfig {
+            path_length: 3,
+            ..config
+        };
+        assert_eq!(
+            collect(&mut Position::Diving(1).unpack_with(config)),
+            vec![0., 1., 0., 0., 0.]
+        );
+        assert_eq!(
+            collect(&mut Position::WaitingToDive.unpack_with(config)),
+            vec![0., 0., 0., 1., 0.]
+        );
+        assert_eq!(
+            collect(&mut Position::ReturnedToSubmarine.unpack_with(config)),
+            vec![0., 0., 0., 0., 1.]
+        );
+
+        // Held treasure slots are one-hot and empty slots are all zero.
+        let player = Player {
+            direction: DiveDirection::Up,
+            position: Position::Diving(2),
+            held_treasures: vec![Treasure::Two],
+            history: History::default(),
+        };
+        let unpacked = collect(&mut player.unpack_with(config));
+        assert_eq!(unpacked.len(), 1 + 5 + 4 * 10);
+        assert_eq!(unpacked[..10], [1., 0., 0., 1., 0., 0., 0., 1., 0., 0.]);
+        assert!(unpacked[10..].iter().all(|&x| x == 0.));
+    }
+
+    #[test]
+    fn test_normalized_layout() {
+        let config = EncoderConfig {
+            path_length: 5,
+            ..EncoderConfig::uniform(Encoding::Normalized)
+        };
+        let collect = |iter: &mut dyn Iterator<Item = f32>| iter.collect::<Vec<_>>();
+
+        assert_eq!(collect(&mut Treasure::One.unpack_with(config)), vec![0.25]);
+        assert_eq!(collect(&mut Treasure::Four.unpack_with(config)), vec![1.]);
+        assert_eq!(collect(&mut Tile::Empty.unpack_with(config)), vec![0.]);
+        assert_eq!(
+            collect(&mut Tile::Treasure(Treasure::Two).unpack_with(config)),
+            vec![0.5]
+        );
+        assert_eq!(
+            collect(&mut Position::Diving(4).unpack_with(config)),
+            vec![1., 0., 0.]
+        );
+        assert_eq!(
+            collect(&mut Position::WaitingToDive.unpack_with(config)),
+            vec![0., 1., 0.]
+        );
+
+        let state = DeepSeaState::default();
+        assert!(
+            state
+                .unpack_with::<f32>(EncoderConfig::uniform(Encoding::Normalized))
+                .all(|x| (0. ..=1.).contains(&x))
+        );
+    }
+
+    #[test]
+    fn test_encodings_match_unpacked_size() {
+        let mut deep_sea = DeepSea::new(Engine::default_path(), 3);
+        deep_sea.move_player(DiveDirection::Down, 4).unwrap();
+        deep_sea.take_treasure(TreasureDecision::Take).unwrap();
+        let state = DeepSeaState::from(&deep_sea);
+        let action = DeepSeaAction::from(TreasureDecision::Take);
+        let state_action = DeepSeaStateActionPair {
+            state: &state,
+            action: &action,
+        };
+        assert_eq!(DEFAULT_PATH_LENGTH, Engine::default_path().len());
+
+        for encoding in [Encoding::Raw, Encoding::OneHot, Encoding::Normalized] {
+            let config = EncoderConfig::uniform(encoding);
+            assert_eq!(
+                state_action.unpack_with::<f32>(config).count(),
+                state_action.unpacked_size_with(config),
+                "{encoding:?}"
+            );
+            for tile in [Tile::Empty, Tile::Treasure(Treasure::Four)] {
+                assert_eq!(
+                    tile.unpack_with::<f32>(config).count(),
+                    tile.unpacked_size_with(config)
+                );
+            }
+        }
+        // Types are encoded independently of each other.
+        let config = EncoderConfig {
+            tile: Encoding::OneHot,
+            ..EncoderConfig::default()
+        };
+        assert_eq!(
+            state.unpacked_size_with(config),
+            state.unpacked_size() + 4 * DEFAULT_PATH_LENGTH
+        );
+        assert_eq!(
+            state.unpack_with::<f32>(config).count(),
+            state.unpacked_size_with(config)
+        );
+    }
+
+    #[test]
+    fn test_schema() {
+        let state = DeepSeaState::default();
+        let action = DeepSeaAction::from(TreasureDecision::Return(Treasure::Three));
+        let state_action = DeepSeaStateActionPair {
+            state: &state,
+            action: &action,
+        };
+        let configs = [Encoding::Raw, Encoding::OneHot, Encoding::Normalized]
+            .map(EncoderConfig::uniform)
+            .into_iter()
+            .chain([
+                EncoderConfig::padded(),
+                EncoderConfig::padded().with_history(2),
+            ]);
+        for config in configs {
+            let schema = state_action.schema(config);
+            assert_eq!(schema.len(), state_action.unpacked_size_with(config));
+            assert_eq!(schema.to_string().lines().count(), schema.len());
+        }
+
+        let schema = state_action.schema(EncoderConfig::default());
+        let features = schema.features();
+        assert_eq!(features[0].name, "path.tiles[0]");
+        assert_eq!(features[0].kind, FeatureKind::Integer);
+        assert_eq!(features[1].name, "path.occupied[0]");
+        let slot = schema.position("players[2].held_treasures[3]").unwrap();
+        // 32 tiles, then 14 features per player of which the 5th is the first held treasure.
+        assert_eq!(slot, 64 + 2 * 14 + 4 + 3);
+        assert_eq!(features[features.len() - 4].name, "action=Return(Three)");
+
+        let values = state_action.unpack::<f32>().collect::<Vec<_>>();
+        let description = schema.describe(&values);
+        assert!(description.contains("path.tiles[31] = 4\n"));
+        assert!(description.contains("players[5].position=WaitingToDive = 1\n"));
+        assert!(description.contains("oxygen = 25\n"));
+        assert!(description.contains("player_idx=0 = 1\n"));
+        assert!(description.contains("action=Return(Three) = 1\n"));
+        assert!(!description.contains("path.occupied"));
+
+        let schema = Treasure::Two.schema(EncoderConfig::uniform(Encoding::OneHot));
+        assert_eq!(
+            schema.decode(&[0., 1., 0., 0.]).nth(1).unwrap().0.name,
+            "=Two"
+        );
+    }
+
+    /// States reached by random play over several seeded games.
+    fn random_states(seed: u64) -> Vec<DeepSeaState> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut states = vec![];
+        for num_players in 2..=6 {
+            let mut env = DeepSeaEnv::new(num_players).unwrap();
+            while env.pending().is_some() {
+                states.push(DeepSeaState::from(env.state()));
+                let action = *env.legal_actions().choose(&mut rng).unwrap();
+                env.step(action).unwrap();
+            }
+            states.push(DeepSeaState::from(env.state()));
+        }
+        states
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        let configs = [Encoding::Raw, Encoding::OneHot, Encoding::Normalized]
+            .into_iter()
+            .map(EncoderConfig::uniform)
+            .chain([
+                EncoderConfig {
+                    tile: Encoding::OneHot,
+                    oxygen: Encoding::Normalized,
+                    ..EncoderConfig::default()
+                },
+                EncoderConfig {
+                    position: Encoding::OneHot,
+                    ..EncoderConfig::padded()
+                },
+                EncoderConfig::padded().with_history(3),
+                EncoderConfig::uniform(Encoding::OneHot).with_history(0),
+            ]);
+        for config in configs {
+            for mut state in random_states(7) {
+                let values = state.unpack_with::<f32>(config).collect::<Vec<_>>();
+                let packed = DeepSeaState::pack_with(&values, state.shape(), config).unwrap();
+                state.forget_history(config);
+                assert_eq!(packed, state, "{config:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_history_features() -> DeepSeaResult {
+        let mut deep_sea = DeepSea::new(Engine::default_path(), 2);
+        deep_sea.move_player(DiveDirection::Down, 2)?;
+        deep_sea.take_treasure(TreasureDecision::Take)?;
+        deep_sea.move_player(DiveDirection::Down, 3)?;
+        deep_sea.take_treasure(TreasureDecision::Ignore)?;
+        deep_sea.move_player(DiveDirection::Up, 3)?;
+        deep_sea.take_treasure(TreasureDecision::Return(Treasure::One))?;
+        deep_sea.next_player();
+
+        let state = Perspective::acting(&deep_sea).observe(&deep_sea);
+        let history = &state.players[1].history;
+        assert_eq!(history.recent_turns.len(), 3);
+        assert_eq!(history.turned_around, Some(2));
+        assert_eq!(history.treasures_taken, 1);
+        assert_eq!(history.treasures_returned, 1);
+        assert_eq!(state.players[0].history, Default::default());
+
+        let config = EncoderConfig::default().with_history(2);
+        let player = &state.players[1];
+        let values = player.unpack_with::<f32>(config).collect::<Vec<_>>();
+        assert_eq!(values.len(), player.unpacked_size_with(config));
+        let schema = player.schema(config);
+        let described = schema.describe(&values);
+        assert!(described.contains("history.recent_turns[0].direction=Up = 1\n"));
+        assert!(described.contains("history.recent_turns[0].returned = 1\n"));
+        assert!(described.contains("history.recent_turns[1].treasure=Ignore = 1\n"));
+        assert!(described.contains("history.turned_around.turns = 2\n"));
+        assert!(described.contains("history.treasures_taken = 1\n"));
+
+        // Without history, the layout is unchanged.
+        assert_eq!(
+            state.unpacked_size_with(EncoderConfig::padded()),
+            DeepSeaState::default().unpacked_size_with(EncoderConfig::padded())
+        );
+        assert!(
+            state.unpacked_size_with(EncoderConfig::padded().with_history(2))
+                > state.unpacked_size_with(EncoderConfig::padded())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unpack_into() {
+        let configs = [Encoding::Raw, Encoding::OneHot, Encoding::Normalized]
+            .map(EncoderConfig::uniform)
+            .into_iter()
+            .chain([
+                EncoderConfig::padded(),
+                EncoderConfig {
+                    tile: Encoding::OneHot,
+                    ..EncoderConfig::padded().with_history(2)
+                },
+            ]);
+        let action = DeepSeaAction::from(TreasureDecision::Take);
+        for config in configs {
+            for state in random_states(3).into_iter().step_by(3) {
+                let state_action = DeepSeaStateActionPair {
+                    state: &state,
+                    action: &action,
+                };
+                // Every slot must be overwritten.
+                let mut buffer = vec![f32::NAN; state_action.unpacked_size_with(config)];
+                state_action.unpack_into(config, &mut buffer);
+                assert_eq!(
+                    buffer,
+                    state_action.unpack_with::<f32>(config).collect::<Vec<_>>(),
+                    "{config:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Buffer does not fit")]
+    fn test_unpack_into_checks_length() {
+        let state = DeepSeaState::default();
+        let mut buffer = vec![0.; state.unpacked_size() + 1];
+        state.unpack_into(EncoderConfig::default(), &mut buffer);
+    }
+
+    #[test]
+    fn test_round_trip_through_arrays() {
+        for mut state in random_states(11).into_iter().step_by(5) {
+            state.forget_history(EncoderConfig::default());
+            let shape = state.shape();
+            let array = state.clone().into_ndarray::<f64>();
+            assert_eq!(DeepSeaState::from_ndarray(&array, shape).unwrap(), state);
+            let data = state.clone().into_tensordata::<f32>();
+            assert_eq!(
+                DeepSeaState::from_tensordata::<f32>(&data, shape).unwrap(),
+                state
+            );
+        }
+    }
+
+    #[test]
+    fn test_action_round_trip() {
+        for idx in 0..DEEP_SEA_ACTION_COUNT {
+            let action = DeepSeaAction::from_index(idx).unwrap();
+            for config in [Encoding::Raw, Encoding::OneHot].map(EncoderConfig::uniform) {
+                let values = action.unpack_with::<f32>(config).collect::<Vec<_>>();
+                assert_eq!(
+                    DeepSeaAction::pack_with(&values, (), config).unwrap(),
+                    action
+                );
+            }
+        }
+        // Model scores decode to the best scored action.
+        let logits = [-1.0f32, 0.5, -3.0, 2.0, 0.0, 1.0, -0.5, 1.5];
+        assert_eq!(
+            DeepSeaAction::pack(&logits, ()).unwrap(),
+            DeepSeaAction::TreasureDecision(TreasureDecision::Return(Treasure::Two))
+        );
+    }
+
+    #[test]
+    fn test_pack_rejects_malformed() {
+        let state = DeepSeaState::default();
+        let values = state.unpack::<f32>().collect::<Vec<_>>();
+        // Too short and too long.
+        assert!(DeepSeaState::pack(&values[1..], state.shape()).is_err());
+        let mut longer = values.clone();
+        longer.push(0.);
+        assert!(DeepSeaState::pack(&longer, state.shape()).is_err());
+        // Nobody to act.
+        let mut no_player = values.clone();
+        no_player[values.len() - 6] = 0.;
+        assert!(DeepSeaState::pack(&no_player, state.shape()).is_err());
+        // Treasure values out of range.
+        assert!(Treasure::pack(&[0.], ()).is_err());
+        assert!(Tile::pack(&[5.], ()).is_err());
+        assert_eq!(
+            StateShape {
+                path_length: 32,
+                num_players: 6
+            },
+            state.shape()
+        );
+    }
+
+    #[test]
+    fn test_batch_vectorization() {
+        let states = random_states(3)
+            .into_iter()
+            .filter(|state| state.players.len() == 4)
+            .collect::<Vec<_>>();
+        let actions = (0..DEEP_SEA_ACTION_COUNT)
+            .map(|idx| DeepSeaAction::from_index(idx).unwrap())
+            .collect::<Vec<_>>();
+        let pairs = states
+            .iter()
+            .zip(actions.iter().cycle())
+            .map(|(state, action)| DeepSeaStateActionPair { state, action })
+            .collect::<Vec<_>>();
+
+        let batch = pairs.to_array2::<f32>();
+        assert_eq!(batch.shape(), &[pairs.len(), pairs[0].unpacked_size()]);
+        for (row, pair) in batch.outer_iter().zip(&pairs) {
+            assert_eq!(row.to_vec(), pair.unpack::<f32>().collect::<Vec<_>>());
+        }
+
+        let data = pairs.to_batch_tensordata::<f32>();
+        assert_eq!(data.shape, batch.shape());
+        assert_eq!(data.to_vec::<f32>().unwrap(), batch.into_raw_vec());
+
+        let no_pairs: &[DeepSeaStateActionPair] = &[];
+        assert_eq!(no_pairs.to_array2::<f32>().shape(), &[0, 0]);
+    }
+
+    #[test]
+    fn test_padded_layout() {
+        let config = EncoderConfig::padded();
+        let states = random_states(5);
+        let width = DeepSeaState::default().unpacked_size_with(config);
+        for state in &states {
+            assert_eq!(state.unpacked_size_with(config), width);
+            assert_eq!(state.unpack_with::<f32>(config).count(), width);
+        }
+
+        let mut deep_sea = DeepSea::new(vec![Tile::Treasure(Treasure::Three), Tile::Empty], 2);
+        deep_sea.move_player(DiveDirection::Down, 2).unwrap();
+        let mut state = DeepSeaState::from(&deep_sea);
+        state.forget_history(config);
+        let schema = state.schema(config);
+        assert_eq!(schema.len(), width);
+        let values = state.unpack_with::<f32>(config).collect::<Vec<_>>();
+        let value = |name: &str| values[schema.position(name).unwrap()];
+
+        // Existing tiles and players are flagged as present, padding is all zero.
+        assert_eq!(value("path.tiles[0]"), 3.);
+        assert_eq!(value("path.present[1]"), 1.);
+        assert_eq!(value("path.occupied[1]"), 1.);
+        assert_eq!(value("path.present[2]"), 0.);
+        assert_eq!(value("players[1].present"), 1.);
+        assert_eq!(value("players[2].present"), 0.);
+        assert_eq!(value("players[2].position=WaitingToDive"), 0.);
+        assert_eq!(value("player_idx=0"), 1.);
+        assert_eq!(value("player_idx=5"), 0.);
+
+        // Padded vectors describe their own shape.
+        let packed = DeepSeaState::pack_with(&values, DeepSeaState::default().shape(), config);
+        assert_eq!(packed.unwrap(), state);
+    }
+
+    #[test]
+    fn test_action_mask() -> DeepSeaResult {
+        let mut deep_sea = DeepSea::new(
+            vec![Tile::Treasure(Treasure::Two), Tile::Empty, Tile::Empty],
+            2,
+        );
+        assert_eq!(
+            ActionMask::directions(&deep_sea)
+                .actions()
+                .collect::<Vec<_>>(),
+            vec![DeepSeaAction::DiveDirection(DiveDirection::Down)]
+        );
+        deep_sea.move_player(DiveDirection::Down, 1)?;
+        deep_sea.take_treasure(TreasureDecision::Take)?;
+        deep_sea.move_player(DiveDirection::Down, 1)?;
+
+        // The mask lines up with the one-hot encoding of every legal action.
+        let mask = ActionMask::treasure_decisions(&deep_sea);
+        let flags = mask.unpack::<f32>().collect::<Vec<_>>();
+        for action in deep_sea.legal_treasure_decisions() {
+            let action = DeepSeaAction::from(action);
+            let one_hot = action.unpack::<f32>().collect::<Vec<_>>();
+            let slot = one_hot.iter().position(|&x| x == 1.).unwrap();
+            assert_eq!(flags[slot], 1.);
+            assert!(mask.is_legal(action));
+        }
+        assert_eq!(mask.as_slice().iter().filter(|&&legal| legal).count(), 2);
+        assert!(!mask.is_legal(TreasureDecision::Take.into()));
+
+        // Argmax skips illegal slots, however high they score.
+        let scores = [0., 9., 0., 1., 0., 0., 9., 9.];
+        assert_eq!(
+            mask.argmax(&scores),
+            Some(TreasureDecision::Return(Treasure::Two).into())
+        );
+        assert_eq!(ActionMask::default().argmax(&scores), None);
+        assert_eq!(
+            mask.schema(EncoderConfig::default()).len(),
+            mask.unpacked_size()
+        );
+
+        Ok(())
+    }
 }
@@ -1,81 +1,159 @@
+use std::time::{Duration, Instant};
+
 use rand::Rng;
 
 use crate::{
+    chance::{ChanceProvider, RngChance},
     deep_sea::{DeepSea, DiveDirection, Position, Tile},
-    error::DeepSeaResult,
+    error::{DeepSeaError, DeepSeaResult},
     random_solver::RandomSolver,
-    solver::{DeepSeaSolver, IntoSolvers},
+    solver::{DeepSeaSolver, IntoSolvers, TreasureDecision},
     treasure::{Treasure, TreasureValueAssigner},
 };
 
-pub struct Engine {
+/// What the engine does when a solver makes an illegal decision or overruns its time limit.
+/// Overruns of solvers in this process are only noticed once their decision returns; solvers
+/// behind a connection, like `TextSolver`, stop waiting for a reply when the limit is up.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum FaultPolicy {
+    /// Ends the game with the error describing the illegal decision.
+    #[default]
+    Abort,
+    /// Plays a legal decision in its place: diving on unless at the end of the path, or ignoring
+    /// the tile. A decision that came too late is discarded the same way.
+    Substitute,
+    /// Takes the seat out of the game. Its diver turns back, ignores every tile from then on and
+    /// scores nothing.
+    Forfeit,
+}
+
+/// Faults of each seat in a game, and how they were handled.
+struct Faults {
+    policy: FaultPolicy,
+    counts: Vec<u32>,
+    forfeited: Vec<bool>,
+}
+
+impl Faults {
+    fn new(policy: FaultPolicy, num_players: usize) -> Self {
+        Self {
+            policy,
+            counts: vec![0; num_players],
+            forfeited: vec![false; num_players],
+        }
+    }
+
+    /// Records the illegal decision `error` by `player_idx`. Returns it if the game should stop,
+    /// otherwise the caller plays the substitute decision.
+    fn report(&mut self, player_idx: usize, error: DeepSeaError) -> DeepSeaResult {
+        self.counts[player_idx] += 1;
+        match self.policy {
+            FaultPolicy::Abort => return Err(error),
+            FaultPolicy::Substitute => {}
+            FaultPolicy::Forfeit => self.forfeited[player_idx] = true,
+        }
+        Ok(())
+    }
+}
+
+/// The outcome of one game.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RoundResult {
+    pub scores: Vec<u32>,
+    /// Illegal or late decisions made by each seat.
+    pub faults: Vec<u32>,
+}
+
+/// The outcome of `Engine::evaluate_solvers`, indexed by solver.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Evaluation {
+    /// The share of games won, with ties splitting the win.
+    pub win_ratios: Vec<f32>,
+    /// Illegal or late decisions made over all games.
+    pub faults: Vec<u64>,
+}
+
+pub struct Engine<C = RngChance> {
     state: DeepSea,
     players: Vec<Box<dyn DeepSeaSolver>>,
+    /// Rolls the dice and reveals chip values.
+    chance: C,
+    faults: Faults,
+    /// Wall-clock budget for each solver decision.
+    time_limit: Option<Duration>,
+}
+
+/// Asks `solver` for one decision, checking it against `time_limit` and for an error the solver
+/// reports itself. Solvers are not interrupted; an overrun is reported once the decision
+/// returns, so a seat which forfeits is never waited on again.
+fn timed_decision<T>(
+    solver: &mut dyn DeepSeaSolver,
+    player_idx: usize,
+    time_limit: Option<Duration>,
+    decide: impl FnOnce(&mut dyn DeepSeaSolver) -> T,
+) -> DeepSeaResult<T> {
+    if let Some(limit) = time_limit {
+        solver.set_time_budget(limit);
+    }
+    let start = Instant::now();
+    let decision = decide(solver);
+    let elapsed = start.elapsed();
+    if let Some(error) = solver.take_error() {
+        return Err(error);
+    }
+    match time_limit {
+        Some(limit) if elapsed > limit => Err(DeepSeaError::Timeout {
+            player_idx,
+            elapsed,
+            limit,
+        }),
+        _ => Ok(decision),
+    }
+}
+
+/// Where a forfeited seat's diver heads: into the water if they have not left yet, so that they
+/// can turn back.
+fn forfeit_direction(position: Position) -> DiveDirection {
+    match position {
+        Position::WaitingToDive => DiveDirection::Down,
+        _ => DiveDirection::Up,
+    }
 }
 
 impl Engine {
     pub fn new(path: Vec<Tile>, players: Vec<Box<dyn DeepSeaSolver>>) -> Self {
         Self {
             state: DeepSea::new(path, players.len()),
+            faults: Faults::new(FaultPolicy::default(), players.len()),
             players,
+            chance: RngChance::default(),
+            time_limit: None,
         }
     }
 
+    pub fn default_path() -> Vec<Tile> {
+        (0..8)
+            .map(|_| Tile::Treasure(Treasure::One))
+            .chain((0..8).map(|_| Tile::Treasure(Treasure::Two)))
+            .chain((0..8).map(|_| Tile::Treasure(Treasure::Three)))
+            .chain((0..8).map(|_| Tile::Treasure(Treasure::Four)))
+            .collect()
+    }
+
     pub fn make_default_game(players: Vec<Box<dyn DeepSeaSolver>>) -> Self {
-        Self::new(
-            (0..8)
-                .map(|_| Tile::Treasure(Treasure::One))
-                .chain((0..8).map(|_| Tile::Treasure(Treasure::Two)))
-                .chain((0..8).map(|_| Tile::Treasure(Treasure::Three)))
-                .chain((0..8).map(|_| Tile::Treasure(Treasure::Four)))
-                .collect(),
-            players,
-        )
+        Self::new(Self::default_path(), players)
     }
 
-    fn roll_dice() -> u32 {
-        let mut rng = rand::rng();
+    pub(crate) fn roll_dice_with(rng: &mut impl Rng) -> u32 {
         let d1 = rng.random_range(1..=3);
         let d2 = rng.random_range(1..=3);
         d1 + d2
     }
 
-    fn take_turn(&mut self) -> DeepSeaResult {
-        let player_idx = self.state.player_idx();
-        let player = &self.state.players()[player_idx];
-        if player.position() == Position::ReturnedToSubmarine {
-            self.state.next_player();
-            return Ok(());
-        }
-
-        self.state.take_oxygen();
-
-        let player_agent = &mut self.players[player_idx];
-
-        let player = &self.state.players()[player_idx];
-        let direction = if player.direction() == DiveDirection::Down {
-            player_agent.choose_direction(&self.state, player_idx)
-        } else {
-            DiveDirection::Up
-        };
-
-        let dice_roll = Self::roll_dice();
-        self.state.move_player(direction, dice_roll)?;
-
-        let player = &self.state.players()[player_idx];
-        if let Position::Diving(_) = player.position() {
-            self.state
-                .take_treasure(player_agent.take_treasure(&self.state, player_idx))?;
-        }
-
-        self.state.next_player();
-        Ok(())
-    }
-
-    fn score_game(&self) -> Vec<u32> {
-        debug_assert!(self.state.done());
+    pub(crate) fn score_with(state: &DeepSea, chance: &mut impl ChanceProvider) -> Vec<u32> {
+        debug_assert!(state.done());
         let mut value_assigner = TreasureValueAssigner::new();
-        self.state
+        state
             .players()
             .iter()
             .map(|player| {
@@ -83,7 +161,7 @@ impl Engine {
                     player
                         .held_treasures()
                         .iter()
-                        .map(|&treasure| value_assigner.assign_value(treasure))
+                        .map(|&treasure| value_assigner.assign_value_with(treasure, chance))
                         .sum()
                 } else {
                     0
@@ -92,24 +170,29 @@ impl Engine {
             .collect()
     }
 
-    pub fn play_one_round(mut self) -> DeepSeaResult<Vec<u32>> {
-        while !self.state.done() {
-            self.take_turn()?;
-        }
-
-        Ok(self.score_game())
-    }
-
-    pub fn play_three_rounds(&mut self) -> DeepSeaResult {
-        todo!();
-    }
-
-    pub fn evaluate_solvers<S: IntoSolvers>(iterations: u64) -> DeepSeaResult<Vec<f32>> {
+    /// Plays `iterations` games between the solvers of `S` in shuffled seats, each decision
+    /// limited to `time_limit` if given.
+    pub fn evaluate_solvers<S: IntoSolvers>(
+        iterations: u64,
+        fault_policy: FaultPolicy,
+        time_limit: Option<Duration>,
+    ) -> DeepSeaResult<Evaluation> {
         let mut ratios = vec![0.; S::num_solvers()];
+        let mut faults = vec![0; S::num_solvers()];
         for _ in 0..iterations {
             let (solvers, idx_map) = S::initialize_shuffled_solvers();
 
-            let result = Self::make_default_game(solvers).play_one_round()?;
+            let mut engine = Self::make_default_game(solvers).with_fault_policy(fault_policy);
+            if let Some(limit) = time_limit {
+                engine = engine.with_time_limit(limit);
+            }
+            let RoundResult {
+                scores: result,
+                faults: game_faults,
+            } = engine.play_round()?;
+            for (&idx, count) in idx_map.iter().zip(game_faults) {
+                faults[idx] += u64::from(count);
+            }
             let max_score = result.iter().cloned().max().unwrap();
             let highest_players = result.iter().filter(|&&score| score == max_score).count();
             for idx in result
@@ -121,10 +204,13 @@ impl Engine {
             }
         }
 
-        Ok(ratios
-            .into_iter()
-            .map(|ratio| ratio as f32 / iterations as f32)
-            .collect())
+        Ok(Evaluation {
+            win_ratios: ratios
+                .into_iter()
+                .map(|ratio| ratio as f32 / iterations as f32)
+                .collect(),
+            faults,
+        })
     }
 
     pub fn play_game() -> DeepSeaResult {
@@ -151,3 +237,413 @@ impl Engine {
         Ok(())
     }
 }
+
+impl<C: ChanceProvider> Engine<C> {
+    /// The same game with its chance outcomes drawn from `chance`, e.g. a seeded `RngChance`
+    /// or a `ScriptedChance` replaying a `ChanceLog`.
+    pub fn with_chance<D: ChanceProvider>(self, chance: D) -> Engine<D> {
+        Engine {
+            state: self.state,
+            players: self.players,
+            chance,
+            faults: self.faults,
+            time_limit: self.time_limit,
+        }
+    }
+
+    /// Gives each solver `time_limit` per decision, applying the fault policy to overruns. The
+    /// engine does not interrupt a solver: one in this process that never returns stalls the
+    /// game, and only solvers honoring `DeepSeaSolver::set_time_budget` give up on time.
+    pub fn with_time_limit(mut self, time_limit: Duration) -> Self {
+        self.time_limit = Some(time_limit);
+        self
+    }
+
+    pub fn with_fault_policy(mut self, policy: FaultPolicy) -> Self {
+        self.faults.policy = policy;
+        self
+    }
+
+    pub fn state(&self) -> &DeepSea {
+        &self.state
+    }
+
+    fn take_turn(&mut self) -> DeepSeaResult {
+        self.play_turn(None)
+    }
+
+    /// Takes the current player's turn with `dice_roll` instead of a roll from the engine's
+    /// `ChanceProvider`. The roll goes unused if the player is already back in the submarine.
+    pub fn take_turn_with_roll(&mut self, dice_roll: u32) -> DeepSeaResult {
+        self.play_turn(Some(dice_roll))
+    }
+
+    fn play_turn(&mut self, dice_roll: Option<u32>) -> DeepSeaResult {
+        let player_idx = self.state.player_idx();
+        let player = &self.state.players()[player_idx];
+        if player.position() == Position::ReturnedToSubmarine {
+            self.state.next_player();
+            return Ok(());
+        }
+
+        self.state.take_oxygen();
+
+        let player_agent = &mut self.players[player_idx];
+
+        let player = &self.state.players()[player_idx];
+        let position = player.position();
+        let direction = if player.direction() == DiveDirection::Up {
+            DiveDirection::Up
+        } else if self.faults.forfeited[player_idx] {
+            forfeit_direction(position)
+        } else {
+            let decision = timed_decision(
+                player_agent.as_mut(),
+                player_idx,
+                self.time_limit,
+                |solver| solver.choose_direction(&self.state, player_idx),
+            )
+            .and_then(|direction| {
+                // `move_player` would accept diving on from the last tile, which can stall the
+                // game forever.
+                if self.state.legal_directions().contains(&direction) {
+                    Ok(direction)
+                } else {
+                    Err(DeepSeaError::IllegalDirection {
+                        player_idx,
+                        direction,
+                        position,
+                        state: self.state.snapshot(),
+                    })
+                }
+            });
+            match decision {
+                Ok(direction) => direction,
+                Err(error) => {
+                    self.faults.report(player_idx, error)?;
+                    if self.faults.forfeited[player_idx] {
+                        forfeit_direction(position)
+                    } else {
+                        self.state.legal_directions()[0]
+                    }
+                }
+            }
+        };
+
+        let dice_roll = dice_roll.unwrap_or_else(|| self.chance.roll_dice());
+        if let Err(error) = self.state.move_player(direction, dice_roll) {
+            self.faults.report(player_idx, error)?;
+            let direction = self.state.legal_directions()[0];
+            self.state.move_player(direction, dice_roll)?;
+        }
+
+        let player = &self.state.players()[player_idx];
+        if let Position::Diving(_) = player.position() {
+            let decision = if self.faults.forfeited[player_idx] {
+                TreasureDecision::Ignore
+            } else {
+                match timed_decision(
+                    player_agent.as_mut(),
+                    player_idx,
+                    self.time_limit,
+                    |solver| solver.take_treasure(&self.state, player_idx),
+                ) {
+                    Ok(decision) => decision,
+                    Err(error) => {
+                        self.faults.report(player_idx, error)?;
+                        TreasureDecision::Ignore
+                    }
+                }
+            };
+            if let Err(error) = self.state.take_treasure(decision) {
+                self.faults.report(player_idx, error)?;
+                self.state.take_treasure(TreasureDecision::Ignore)?;
+            }
+        }
+
+        self.state.next_player();
+        Ok(())
+    }
+
+    /// Forfeited seats score nothing.
+    fn score_game(&mut self) -> Vec<u32> {
+        let mut scores = Engine::score_with(&self.state, &mut self.chance);
+        for (score, &forfeited) in scores.iter_mut().zip(&self.faults.forfeited) {
+            if forfeited {
+                *score = 0;
+            }
+        }
+        scores
+    }
+
+    pub fn play_one_round(self) -> DeepSeaResult<Vec<u32>> {
+        Ok(self.play_round()?.scores)
+    }
+
+    /// Plays the game to the end, reporting the faults of each seat along with the scores.
+    pub fn play_round(self) -> DeepSeaResult<RoundResult> {
+        self.play_round_observed(|_| {})
+    }
+
+    /// Plays the game to the end like `play_round`, showing `observe` the state after each turn.
+    pub fn play_round_observed(
+        mut self,
+        mut observe: impl FnMut(&DeepSea),
+    ) -> DeepSeaResult<RoundResult> {
+        while !self.state.done() {
+            self.take_turn()?;
+            observe(&self.state);
+        }
+
+        Ok(RoundResult {
+            scores: self.score_game(),
+            faults: self.faults.counts,
+        })
+    }
+
+    pub fn play_three_rounds(&mut self) -> DeepSeaResult {
+        todo!();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use crate::{
+        chance::{RecordingChance, RngChance, ScriptedChance},
+        deep_sea::{DeepSea, DiveDirection, Position},
+        engine::{Engine, FaultPolicy},
+        error::DeepSeaError,
+        solver::{DeepSeaSolver, TreasureDecision},
+        treasure::Treasure,
+    };
+
+    /// Takes two treasures and heads back, so games only depend on chance.
+    #[derive(Default)]
+    struct TakeTwoSolver;
+
+    impl DeepSeaSolver for TakeTwoSolver {
+        fn choose_direction(&mut self, deep_sea: &DeepSea, player_idx: usize) -> DiveDirection {
+            if deep_sea.players()[player_idx].held_treasures().len() < 2 {
+                DiveDirection::Down
+            } else {
+                DiveDirection::Up
+            }
+        }
+
+        fn take_treasure(&mut self, deep_sea: &DeepSea, player_idx: usize) -> TreasureDecision {
+            if deep_sea
+                .legal_treasure_decisions()
+                .contains(&TreasureDecision::Take)
+                && deep_sea.players()[player_idx].held_treasures().len() < 2
+            {
+                TreasureDecision::Take
+            } else {
+                TreasureDecision::Ignore
+            }
+        }
+    }
+
+    /// Turns back after its first move, always trying to put back a treasure it never holds.
+    #[derive(Default)]
+    struct IllegalSolver;
+
+    impl DeepSeaSolver for IllegalSolver {
+        fn choose_direction(&mut self, deep_sea: &DeepSea, player_idx: usize) -> DiveDirection {
+            match deep_sea.players()[player_idx].position() {
+                Position::WaitingToDive => DiveDirection::Down,
+                _ => DiveDirection::Up,
+            }
+        }
+
+        fn take_treasure(&mut self, _deep_sea: &DeepSea, _player_idx: usize) -> TreasureDecision {
+            TreasureDecision::Return(Treasure::Four)
+        }
+    }
+
+    /// Always dives on and ignores every tile, so it never runs out of oxygen on its own.
+    struct DownSolver;
+
+    impl DeepSeaSolver for DownSolver {
+        fn choose_direction(&mut self, _deep_sea: &DeepSea, _player_idx: usize) -> DiveDirection {
+            DiveDirection::Down
+        }
+
+        fn take_treasure(&mut self, _deep_sea: &DeepSea, _player_idx: usize) -> TreasureDecision {
+            TreasureDecision::Ignore
+        }
+    }
+
+    fn with_illegal_seat() -> Vec<Box<dyn DeepSeaSolver>> {
+        vec![Box::new(TakeTwoSolver), Box::new(IllegalSolver)]
+    }
+
+    /// Plays like `TakeTwoSolver` after sleeping through each decision's time budget.
+    #[derive(Default)]
+    struct SlowSolver {
+        budgets: Vec<Duration>,
+    }
+
+    impl DeepSeaSolver for SlowSolver {
+        fn choose_direction(&mut self, deep_sea: &DeepSea, player_idx: usize) -> DiveDirection {
+            thread::sleep(*self.budgets.last().unwrap() * 2);
+            TakeTwoSolver.choose_direction(deep_sea, player_idx)
+        }
+
+        fn take_treasure(&mut self, deep_sea: &DeepSea, player_idx: usize) -> TreasureDecision {
+            thread::sleep(*self.budgets.last().unwrap() * 2);
+            TakeTwoSolver.take_treasure(deep_sea, player_idx)
+        }
+
+        fn set_time_budget(&mut self, budget: Duration) {
+            self.budgets.push(budget);
+        }
+    }
+
+    fn with_slow_seat() -> Vec<Box<dyn DeepSeaSolver>> {
+        vec![Box::new(TakeTwoSolver), Box::new(SlowSolver::default())]
+    }
+
+    fn take_two_solvers(num_players: usize) -> Vec<Box<dyn DeepSeaSolver>> {
+        (0..num_players)
+            .map(|_| Box::new(TakeTwoSolver) as Box<dyn DeepSeaSolver>)
+            .collect()
+    }
+
+    #[test]
+    fn test_take_turn_with_roll() {
+        let mut engine =
+            Engine::make_default_game(take_two_solvers(2)).with_chance(ScriptedChance::default());
+        engine.take_turn_with_roll(4).unwrap();
+        engine.take_turn_with_roll(6).unwrap();
+        let players = engine.state().players();
+        assert_eq!(players[0].position(), Position::Diving(3));
+        // Skips over the first player's tile.
+        assert_eq!(players[1].position(), Position::Diving(6));
+        assert_eq!(engine.state().player_idx(), 0);
+    }
+
+    #[test]
+    fn test_recorded_game_replays() {
+        let mut recording = RecordingChance::new(RngChance::seeded(7));
+        let scores = Engine::make_default_game(take_two_solvers(3))
+            .with_chance(&mut recording)
+            .play_one_round()
+            .unwrap();
+        let log = recording.into_log();
+        assert!(!log.dice_rolls.is_empty());
+
+        let replayed = Engine::make_default_game(take_two_solvers(3))
+            .with_chance(ScriptedChance::from(log.clone()))
+            .play_one_round()
+            .unwrap();
+        assert_eq!(replayed, scores);
+
+        let reseeded = Engine::make_default_game(take_two_solvers(3))
+            .with_chance(RngChance::seeded(7))
+            .play_one_round()
+            .unwrap();
+        assert_eq!(reseeded, scores);
+    }
+
+    #[test]
+    fn test_abort_on_fault() {
+        let result = Engine::make_default_game(with_illegal_seat())
+            .with_fault_policy(FaultPolicy::Abort)
+            .play_round();
+        assert!(matches!(
+            result,
+            // Its first move ends on a treasure.
+            Err(DeepSeaError::ReturnOntoOccupiedTile { player_idx: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_substitute_on_fault() {
+        let result = Engine::make_default_game(with_illegal_seat())
+            .with_fault_policy(FaultPolicy::Substitute)
+            .play_round()
+            .unwrap();
+        assert_eq!(result.faults[0], 0);
+        assert!(result.faults[1] >= 1);
+    }
+
+    #[test]
+    fn test_forfeit_on_fault() {
+        let result = Engine::make_default_game(with_illegal_seat())
+            .with_fault_policy(FaultPolicy::Forfeit)
+            .play_round()
+            .unwrap();
+        assert_eq!(result.faults, vec![0, 1]);
+        assert_eq!(result.scores[1], 0);
+    }
+
+    #[test]
+    fn test_diving_on_from_the_last_tile_is_a_fault() {
+        let result = Engine::make_default_game(vec![Box::new(DownSolver)])
+            .with_fault_policy(FaultPolicy::Abort)
+            .play_round();
+        assert!(matches!(
+            result,
+            Err(DeepSeaError::IllegalDirection {
+                player_idx: 0,
+                direction: DiveDirection::Down,
+                position: Position::Diving(31),
+                ..
+            })
+        ));
+
+        // Turned back in its place, the diver heads home without being asked again.
+        let result = Engine::make_default_game(vec![Box::new(DownSolver)])
+            .with_fault_policy(FaultPolicy::Substitute)
+            .play_round()
+            .unwrap();
+        assert_eq!(result.faults, [1]);
+    }
+
+    #[test]
+    fn test_evaluation_counts_faults_per_solver() {
+        let evaluation = Engine::evaluate_solvers::<(TakeTwoSolver, IllegalSolver)>(
+            10,
+            FaultPolicy::Forfeit,
+            None,
+        )
+        .unwrap();
+        assert_eq!(evaluation.faults, vec![0, 10]);
+        assert_eq!(evaluation.win_ratios.len(), 2);
+    }
+
+    #[test]
+    fn test_time_limit() {
+        let limit = Duration::from_millis(5);
+        let result = Engine::make_default_game(with_slow_seat())
+            .with_fault_policy(FaultPolicy::Abort)
+            .with_time_limit(limit)
+            .play_round();
+        assert!(matches!(
+            result,
+            Err(DeepSeaError::Timeout { player_idx: 1, elapsed, limit: l }) if elapsed > limit && l == limit
+        ));
+
+        // The slow seat is only waited on once.
+        let result = Engine::make_default_game(with_slow_seat())
+            .with_fault_policy(FaultPolicy::Forfeit)
+            .with_time_limit(limit)
+            .play_round()
+            .unwrap();
+        assert_eq!(result.faults, vec![0, 1]);
+        assert_eq!(result.scores[1], 0);
+    }
+
+    #[test]
+    fn test_time_limit_substitutes_late_decisions() {
+        let result = Engine::make_default_game(with_slow_seat())
+            .with_fault_policy(FaultPolicy::Substitute)
+            .with_time_limit(Duration::from_millis(2))
+            .play_round()
+            .unwrap();
+        assert_eq!(result.faults[0], 0);
+        assert!(result.faults[1] >= 1);
+    }
+}
@@ -1,12 +1,109 @@
 use std::{
     error::Error,
     fmt::{self, Display, Formatter},
+    io,
+    time::Duration,
 };
 
+use burn::{config::ConfigError, record::RecorderError};
+
+use crate::{
+    deep_sea::{DiveDirection, Position, Tile, compact::CompactDeepSea},
+    deep_sea_vectorization::DeepSeaAction,
+    ml::env::PendingDecision,
+    treasure::Treasure,
+};
+
+/// Rule violations carry the index of the player who broke the rule, what they broke it on and
+/// a snapshot of the game, all as they were just before the offending move. The snapshot is
+/// packed to keep errors cheap, and is `None` for games too large for `CompactDeepSea`.
 #[derive(Debug)]
 pub enum DeepSeaError {
     Internal(String),
     AgentError(String),
+    /// Moving up before leaving the submarine, or diving on from the last tile.
+    IllegalDirection {
+        player_idx: usize,
+        direction: DiveDirection,
+        position: Position,
+        state: Option<Box<CompactDeepSea>>,
+    },
+    TakeFromEmptyTile {
+        player_idx: usize,
+        tile_idx: usize,
+        state: Option<Box<CompactDeepSea>>,
+    },
+    ReturnOntoOccupiedTile {
+        player_idx: usize,
+        tile_idx: usize,
+        tile: Tile,
+        state: Option<Box<CompactDeepSea>>,
+    },
+    TreasureNotHeld {
+        player_idx: usize,
+        treasure: Treasure,
+        /// The treasures the player did hold.
+        held: Vec<Treasure>,
+        state: Option<Box<CompactDeepSea>>,
+    },
+    MoveAfterReturning {
+        player_idx: usize,
+        state: Option<Box<CompactDeepSea>>,
+    },
+    /// An environment was stepped with an action that does not answer the decision it awaits,
+    /// or without one while a decision was owed.
+    UnexpectedAction {
+        /// The game within a batch of environments.
+        env_idx: Option<usize>,
+        pending: Option<PendingDecision>,
+        action: Option<DeepSeaAction>,
+    },
+    /// A solver took longer than the engine's time limit to decide.
+    Timeout {
+        player_idx: usize,
+        elapsed: Duration,
+        limit: Duration,
+    },
+    /// A solver in another process broke the text protocol or stopped answering.
+    Protocol {
+        player_idx: Option<usize>,
+        message: String,
+    },
+    /// Settings, command line arguments or checkpoints that do not fit together.
+    InvalidConfig(String),
+    /// Recorded game data, such as packed features, that does not decode.
+    CorruptReplay(String),
+    Io(io::Error),
+    /// Saving or loading a model or its config failed.
+    Checkpoint(Box<dyn Error + Send + Sync>),
+}
+
+impl DeepSeaError {
+    /// The player at fault, for rule violations and timeouts.
+    pub fn player_idx(&self) -> Option<usize> {
+        match self {
+            DeepSeaError::IllegalDirection { player_idx, .. }
+            | DeepSeaError::TakeFromEmptyTile { player_idx, .. }
+            | DeepSeaError::ReturnOntoOccupiedTile { player_idx, .. }
+            | DeepSeaError::TreasureNotHeld { player_idx, .. }
+            | DeepSeaError::MoveAfterReturning { player_idx, .. }
+            | DeepSeaError::Timeout { player_idx, .. } => Some(*player_idx),
+            DeepSeaError::Protocol { player_idx, .. } => *player_idx,
+            _ => None,
+        }
+    }
+
+    /// The game before the offending move, for rule violations.
+    pub fn state(&self) -> Option<&CompactDeepSea> {
+        match self {
+            DeepSeaError::IllegalDirection { state, .. }
+            | DeepSeaError::TakeFromEmptyTile { state, .. }
+            | DeepSeaError::ReturnOntoOccupiedTile { state, .. }
+            | DeepSeaError::TreasureNotHeld { state, .. }
+            | DeepSeaError::MoveAfterReturning { state, .. } => state.as_deref(),
+            _ => None,
+        }
+    }
 }
 
 impl Display for DeepSeaError {
@@ -14,10 +111,109 @@ impl Display for DeepSeaError {
         match self {
             DeepSeaError::Internal(msg) => write!(f, "Internal error: {msg}"),
             DeepSeaError::AgentError(msg) => write!(f, "Agent error: {msg}"),
+            DeepSeaError::IllegalDirection {
+                player_idx,
+                direction,
+                position,
+                ..
+            } => write!(
+                f,
+                "Player {player_idx} cannot move {direction:?} from {position:?}"
+            ),
+            DeepSeaError::TakeFromEmptyTile {
+                player_idx,
+                tile_idx,
+                ..
+            } => write!(
+                f,
+                "Player {player_idx} cannot take treasure from empty tile {tile_idx}"
+            ),
+            DeepSeaError::ReturnOntoOccupiedTile {
+                player_idx,
+                tile_idx,
+                tile,
+                ..
+            } => write!(
+                f,
+                "Player {player_idx} cannot put treasure back in non-empty tile {tile_idx}: {tile:?}"
+            ),
+            DeepSeaError::TreasureNotHeld {
+                player_idx,
+                treasure,
+                held,
+                ..
+            } => write!(
+                f,
+                "Player {player_idx} is not holding treasure {treasure:?}: {held:?}"
+            ),
+            DeepSeaError::MoveAfterReturning { player_idx, .. } => write!(
+                f,
+                "Player {player_idx} cannot move after returning to the submarine"
+            ),
+            DeepSeaError::UnexpectedAction {
+                env_idx,
+                pending,
+                action,
+            } => {
+                write!(
+                    f,
+                    "Action {action:?} does not answer pending decision {pending:?}"
+                )?;
+                if let Some(env_idx) = env_idx {
+                    write!(f, " in game {env_idx}")?;
+                }
+                Ok(())
+            }
+            DeepSeaError::Timeout {
+                player_idx,
+                elapsed,
+                limit,
+            } => write!(
+                f,
+                "Player {player_idx} took {elapsed:?} to decide, over the limit of {limit:?}"
+            ),
+            DeepSeaError::Protocol {
+                player_idx: Some(player_idx),
+                message,
+            } => write!(f, "Protocol error from player {player_idx}: {message}"),
+            DeepSeaError::Protocol {
+                player_idx: None,
+                message,
+            } => write!(f, "Protocol error: {message}"),
+            DeepSeaError::InvalidConfig(msg) => write!(f, "Invalid config: {msg}"),
+            DeepSeaError::CorruptReplay(msg) => write!(f, "Corrupt replay: {msg}"),
+            DeepSeaError::Io(err) => write!(f, "I/O error: {err}"),
+            DeepSeaError::Checkpoint(err) => write!(f, "Checkpoint error: {err}"),
         }
     }
 }
 
-impl Error for DeepSeaError {}
+impl Error for DeepSeaError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DeepSeaError::Io(err) => Some(err),
+            DeepSeaError::Checkpoint(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DeepSeaError {
+    fn from(err: io::Error) -> Self {
+        DeepSeaError::Io(err)
+    }
+}
+
+impl From<RecorderError> for DeepSeaError {
+    fn from(err: RecorderError) -> Self {
+        DeepSeaError::Checkpoint(Box::new(err))
+    }
+}
+
+impl From<ConfigError> for DeepSeaError {
+    fn from(err: ConfigError) -> Self {
+        DeepSeaError::Checkpoint(Box::new(err))
+    }
+}
 
-pub type DeepSeaResult<T = ()> = Result<T, Box<dyn Error + Send + Sync>>;
+pub type DeepSeaResult<T = ()> = Result<T, DeepSeaError>;
@@ -1,8 +1,13 @@
+pub mod chance;
 pub mod deep_sea;
 pub mod deep_sea_vectorization;
 pub mod engine;
 pub mod error;
 pub mod ml;
+pub mod process_solver;
+pub mod protocol;
 pub mod random_solver;
+pub mod server;
 pub mod solver;
+pub mod text_solver;
 pub mod treasure;
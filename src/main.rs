@@ -1,6 +1,138 @@
-use deep_sea::{engine::Engine, error::DeepSeaResult, random_solver::RandomSolver};
+use std::{process::Command, time::Duration};
+
+use deep_sea::{
+    engine::{Engine, FaultPolicy},
+    error::{DeepSeaError, DeepSeaResult},
+    ml::{
+        dataset::{Dataset, DatasetFormat},
+        dqn::DqnSolver,
+        ppo::PpoSolver,
+    },
+    process_solver::ProcessSolver,
+    random_solver::RandomSolver,
+    server::GameServer,
+    solver::DeepSeaSolver,
+};
+
+const USAGE: &str = "Usage:
+  deep-sea
+      Evaluates six random solvers against each other.
+  deep-sea dataset --games <n> --out <dir> [--format npy|npz] [--solvers <solver>,...]
+      Records every decision of <n> games between the given solvers (default: six random
+      solvers) into <dir>. A solver is `random`, `dqn:<checkpoint dir>`, `ppo:<checkpoint dir>`
+      or `exec:<program>`, a bot speaking the text protocol over stdin and stdout.
+  deep-sea serve --seats <n> [--port <port>] [--time-limit-ms <ms>]
+      Hosts a game on localhost for the first <n> clients to connect, e.g. bots or people
+      using `nc localhost <port>`. The port defaults to 7878. With a time limit, a client
+      that has not replied in time forfeits its seat, as does one that breaks the protocol;
+      without one, the server waits on each client for as long as it takes. Clients that do
+      not answer the handshake within 10 seconds are turned away.";
+
+fn usage_error(msg: impl std::fmt::Display) -> DeepSeaError {
+    DeepSeaError::InvalidConfig(format!("{msg}\n{USAGE}"))
+}
+
+fn parse_solver(spec: &str) -> DeepSeaResult<Box<dyn DeepSeaSolver>> {
+    match spec.split_once(':') {
+        None if spec == "random" => Ok(Box::new(RandomSolver)),
+        Some(("dqn", dir)) => Ok(Box::new(<DqnSolver>::load(dir, Default::default())?)),
+        Some(("ppo", dir)) => Ok(Box::new(<PpoSolver>::load(dir, Default::default())?)),
+        Some(("exec", program)) => Ok(Box::new(ProcessSolver::spawn(&mut Command::new(program))?)),
+        _ => Err(usage_error(format!("Unknown solver `{spec}`"))),
+    }
+}
+
+fn generate_dataset(args: &[String]) -> DeepSeaResult {
+    let mut num_games = None;
+    let mut out = None;
+    let mut format = DatasetFormat::Npz;
+    let mut solvers = vec!["random"; 6];
+    for pair in args.chunks(2) {
+        let [flag, value] = pair else {
+            return Err(usage_error(format!("Missing value for `{}`", pair[0])));
+        };
+        match flag.as_str() {
+            "--games" => {
+                num_games = Some(
+                    value
+                        .parse::<usize>()
+                        .map_err(|e| usage_error(format!("Invalid --games: {e}")))?,
+                )
+            }
+            "--out" => out = Some(value),
+            "--format" => {
+                format = match value.as_str() {
+                    "npy" => DatasetFormat::Npy,
+                    "npz" => DatasetFormat::Npz,
+                    _ => return Err(usage_error(format!("Unknown format `{value}`"))),
+                }
+            }
+            "--solvers" => solvers = value.split(',').collect(),
+            _ => return Err(usage_error(format!("Unknown flag `{flag}`"))),
+        }
+    }
+    let num_games = num_games.ok_or_else(|| usage_error("Missing --games"))?;
+    let out = out.ok_or_else(|| usage_error("Missing --out"))?;
+
+    let dataset = Dataset::generate(num_games, || {
+        solvers.iter().map(|spec| parse_solver(spec)).collect()
+    })?;
+    dataset.save(out, format)?;
+    println!(
+        "Wrote {} decisions from {num_games} games to {out}",
+        dataset.len()
+    );
+
+    Ok(())
+}
+
+fn serve(args: &[String]) -> DeepSeaResult {
+    let mut num_seats = None;
+    let mut port = 7878;
+    let mut time_limit = None;
+    for pair in args.chunks(2) {
+        let [flag, value] = pair else {
+            return Err(usage_error(format!("Missing value for `{}`", pair[0])));
+        };
+        let number = value
+            .parse::<u64>()
+            .map_err(|e| usage_error(format!("Invalid {flag}: {e}")))?;
+        match flag.as_str() {
+            "--seats" => num_seats = Some(number as usize),
+            "--port" => {
+                port = u16::try_from(number)
+                    .map_err(|e| usage_error(format!("Invalid --port: {e}")))?
+            }
+            "--time-limit-ms" => time_limit = Some(Duration::from_millis(number)),
+            _ => return Err(usage_error(format!("Unknown flag `{flag}`"))),
+        }
+    }
+    let num_seats = num_seats.ok_or_else(|| usage_error("Missing --seats"))?;
+
+    let mut server = GameServer::bind(("127.0.0.1", port))?.with_fault_policy(FaultPolicy::Forfeit);
+    if let Some(time_limit) = time_limit {
+        server = server.with_time_limit(time_limit);
+    }
+    println!(
+        "Waiting for {num_seats} players on {}",
+        server.local_addr()?
+    );
+    let result = server.host_round(Engine::default_path(), num_seats)?;
+    println!("Scores: {:?}", result.scores);
+    println!("Faults: {:?}", result.faults);
+
+    Ok(())
+}
 
 fn run() -> DeepSeaResult {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("dataset") => return generate_dataset(&args[1..]),
+        Some("serve") => return serve(&args[1..]),
+        Some(arg) => return Err(usage_error(format!("Unknown command `{arg}`"))),
+        None => {}
+    }
+
     // let result = Engine::play_game();
     let result = Engine::evaluate_solvers::<(
         RandomSolver,
@@ -9,9 +141,10 @@ fn run() -> DeepSeaResult {
         RandomSolver,
         RandomSolver,
         RandomSolver,
-    )>(1_000_000)?;
+    )>(1_000_000, FaultPolicy::Abort, None)?;
 
-    println!("Result: {result:?}");
+    println!("Result: {:?}", result.win_ratios);
+    println!("Faults: {:?}", result.faults);
 
     Ok(())
 }
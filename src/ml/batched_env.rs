@@ -0,0 +1,372 @@
+use burn::prelude::*;
+use itertools::Itertools;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+use crate::{
+    chance::RngChance,
+    deep_sea::{DeepSea, DiveDirection, Position, compact::CompactDeepSea},
+    deep_sea_vectorization::{
+        ActionMask, DeepSeaAction, DeepSeaState, EncoderConfig, Perspective, Player,
+    },
+    engine::Engine,
+    error::{DeepSeaError, DeepSeaResult},
+    ml::{
+        env::PendingDecision,
+        vectorization::{DataType, Unpackable},
+    },
+};
+
+/// Many games of the same size advanced in lockstep, so that stepping and vectorizing the whole
+/// batch stays free of per-game allocations. Follows the same turn order as `DeepSeaEnv`, and
+/// like it rolls each game's dice and draws its chip values from its own seeded generator.
+///
+/// The board is stored as an array of structs rather than one array per field: each game is a
+/// `CompactDeepSea`, a `Copy` state of two cache lines without heap storage, so the batch is
+/// still one contiguous allocation and is stepped by the compact rules rather than a copy of
+/// them. Only the bookkeeping those rules do not touch is kept in arrays of its own.
+pub struct BatchedDeepSeaEnv {
+    num_players: usize,
+    path_len: usize,
+    /// `[num_envs]`, one packed state per game.
+    games: Vec<CompactDeepSea>,
+    /// `[num_envs]`.
+    pending: Vec<Option<PendingDecision>>,
+    /// `[num_envs]`.
+    rngs: Vec<StdRng>,
+    /// `[num_envs, num_players]`, revealed once each game ends.
+    scores: Vec<u32>,
+}
+
+impl BatchedDeepSeaEnv {
+    /// `num_envs` games on `Engine::default_path`, whose dice are reproducible from `seed`.
+    pub fn new(num_envs: usize, num_players: usize, seed: u64) -> DeepSeaResult<Self> {
+        let path = Engine::default_path();
+        let game = CompactDeepSea::new(&path, num_players)?;
+        let mut seeds = StdRng::seed_from_u64(seed);
+        let mut env = Self {
+            num_players,
+            path_len: path.len(),
+            games: vec![game; num_envs],
+            pending: vec![None; num_envs],
+            rngs: (0..num_envs)
+                .map(|_| StdRng::seed_from_u64(seeds.random()))
+                .collect(),
+            scores: vec![0; num_envs * num_players],
+        };
+        for idx in 0..num_envs {
+            env.advance(idx)?;
+        }
+        Ok(env)
+    }
+
+    pub fn num_envs(&self) -> usize {
+        self.games.len()
+    }
+
+    pub fn num_players(&self) -> usize {
+        self.num_players
+    }
+
+    /// The decision owed in each game, `None` for games that are over.
+    pub fn pending(&self) -> &[Option<PendingDecision>] {
+        &self.pending
+    }
+
+    pub fn done(&self) -> bool {
+        self.pending.iter().all(Option::is_none)
+    }
+
+    /// The player to act in game `idx`.
+    pub fn player_idx(&self, idx: usize) -> usize {
+        self.games[idx].player_idx()
+    }
+
+    /// Applies one action per game, `None` exactly for the games that are over. Panics unless
+    /// `actions` holds one entry per game, like `observations_into` for a misfit buffer.
+    pub fn step(&mut self, actions: &[Option<DeepSeaAction>]) -> DeepSeaResult {
+        assert_eq!(
+            actions.len(),
+            self.num_envs(),
+            "Expected one action per game"
+        );
+        // Checked up front so that a malformed batch leaves every game untouched.
+        if let Some(idx) =
+            (0..self.num_envs()).find(|&idx| actions[idx].is_some() != self.pending[idx].is_some())
+        {
+            return Err(DeepSeaError::UnexpectedAction {
+                env_idx: Some(idx),
+                pending: self.pending[idx],
+                action: actions[idx],
+            });
+        }
+        for (idx, &action) in actions.iter().enumerate() {
+            if let Some(action) = action {
+                self.step_game(idx, action)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The legal actions of every game, empty for games that are over.
+    pub fn action_masks(&self) -> Vec<ActionMask> {
+        (0..self.num_envs())
+            .map(|idx| self.action_mask(idx))
+            .collect()
+    }
+
+    pub fn action_mask(&self, idx: usize) -> ActionMask {
+        let game = &self.games[idx];
+        match self.pending[idx] {
+            Some(PendingDecision::Direction) => {
+                ActionMask::from_actions(game.legal_directions().iter().copied().map_into())
+            }
+            Some(PendingDecision::Treasure) => {
+                ActionMask::from_actions(game.legal_treasure_decisions().map_into())
+            }
+            None => ActionMask::default(),
+        }
+    }
+
+    /// Final scores of game `idx`, once it is over. Chip values are drawn from the game's own
+    /// generator when it ends, as `DeepSeaEnv` draws them.
+    pub fn scores(&self, idx: usize) -> &[u32] {
+        assert!(self.pending[idx].is_none(), "Game {idx} is not over");
+        &self.scores[idx * self.num_players..(idx + 1) * self.num_players]
+    }
+
+    /// Game `idx` as a vectorizable state, with players in turn order.
+    pub fn state(&self, idx: usize) -> DeepSeaState {
+        let mut state = DeepSeaState::default();
+        self.fill_state(idx, Perspective::new(0, self.num_players), &mut state);
+        state
+    }
+
+    /// Writes one row per game into `out`, each the features of the game from the acting
+    /// player's perspective as `Perspective::observe` gives them. Rows of games that are over
+    /// are all zero. Returns the width of a row.
+    pub fn observations_into<T: DataType>(&self, config: EncoderConfig, out: &mut [T]) -> usize {
+        let mut state = DeepSeaState::default();
+        let width = self.observation_size(config);
+        assert_eq!(
+            out.len(),
+            self.num_envs() * width,
+            "Buffer does not fit the observations"
+        );
+        for (idx, row) in out.chunks_exact_mut(width).enumerate() {
+            if self.pending[idx].is_none() {
+                row.fill(T::zero());
+                continue;
+            }
+            let perspective = Perspective::new(self.games[idx].player_idx(), self.num_players);
+            self.fill_state(idx, perspective, &mut state);
+            state.unpack_into(config, row);
+        }
+        width
+    }
+
+    /// `[num_envs, width]` observations, see `observations_into`.
+    pub fn observations<T: DataType>(&self, config: EncoderConfig) -> TensorData {
+        let mut buffer = vec![T::zero(); self.num_envs() * self.observation_size(config)];
+        let width = self.observations_into(config, &mut buffer);
+        TensorData::new(buffer, [self.num_envs(), width])
+    }
+
+    pub fn observation_size(&self, config: EncoderConfig) -> usize {
+        let mut state = DeepSeaState::default();
+        state.path.tiles.truncate(self.path_len);
+        state.players.resize_with(self.num_players, Player::new);
+        state.unpacked_size_with(config)
+    }
+
+    /// Overwrites `state` with game `idx` seen from `perspective`, reusing its buffers. Turn
+    /// history is not kept.
+    fn fill_state(&self, idx: usize, perspective: Perspective, state: &mut DeepSeaState) {
+        let game = &self.games[idx];
+        state.path.tiles.clear();
+        state
+            .path
+            .tiles
+            .extend((0..self.path_len).map(|tile_idx| game.tile(tile_idx)));
+        state.path.occupied.clear();
+        state.path.occupied.extend(
+            (0..self.path_len).filter(|&tile_idx| game.occupied(Position::Diving(tile_idx))),
+        );
+        state.players.resize_with(self.num_players, Player::new);
+        for (relative, player) in state.players.iter_mut().enumerate() {
+            let player_idx = perspective.to_absolute(relative);
+            player.direction = game.direction(player_idx);
+            player.position = game.position(player_idx);
+            player.held_treasures.clear();
+            player
+                .held_treasures
+                .extend(game.held_treasures(player_idx));
+            player.history = Default::default();
+        }
+        state.oxygen = game.oxygen() as u16;
+        state.player_idx = perspective.to_relative(game.player_idx());
+    }
+
+    /// Mirrors `DeepSeaEnv::step`.
+    fn step_game(&mut self, idx: usize, action: DeepSeaAction) -> DeepSeaResult {
+        let game = &mut self.games[idx];
+        match (self.pending[idx], action) {
+            (Some(PendingDecision::Direction), DeepSeaAction::DiveDirection(direction)) => {
+                let dice_roll = Engine::roll_dice_with(&mut self.rngs[idx]);
+                game.move_player(direction, dice_roll)?;
+                if self.await_treasure(idx) {
+                    return Ok(());
+                }
+            }
+            (Some(PendingDecision::Treasure), DeepSeaAction::TreasureDecision(decision)) => {
+                game.take_treasure(decision)?;
+            }
+            (pending, action) => {
+                return Err(DeepSeaError::UnexpectedAction {
+                    env_idx: Some(idx),
+                    pending,
+                    action: Some(action),
+                });
+            }
+        }
+        self.games[idx].next_player();
+        self.advance(idx)
+    }
+
+    /// Mirrors `DeepSeaEnv::advance`.
+    fn advance(&mut self, idx: usize) -> DeepSeaResult {
+        while !self.games[idx].done() {
+            let game = &mut self.games[idx];
+            let player_idx = game.player_idx();
+            if game.position(player_idx) == Position::ReturnedToSubmarine {
+                game.next_player();
+                continue;
+            }
+
+            game.take_oxygen();
+            if game.direction(player_idx) == DiveDirection::Down {
+                self.pending[idx] = Some(PendingDecision::Direction);
+                return Ok(());
+            }
+
+            let dice_roll = Engine::roll_dice_with(&mut self.rngs[idx]);
+            game.move_player(DiveDirection::Up, dice_roll)?;
+            if self.await_treasure(idx) {
+                return Ok(());
+            }
+            self.games[idx].next_player();
+        }
+        self.pending[idx] = None;
+        let scores = Engine::score_with(
+            &DeepSea::from(&self.games[idx]),
+            &mut RngChance::new(&mut self.rngs[idx]),
+        );
+        self.scores[idx * self.num_players..(idx + 1) * self.num_players].copy_from_slice(&scores);
+        Ok(())
+    }
+
+    /// Mirrors `DeepSeaEnv::await_treasure`.
+    fn await_treasure(&mut self, idx: usize) -> bool {
+        let game = &self.games[idx];
+        let diving = matches!(game.position(game.player_idx()), Position::Diving(_));
+        if diving {
+            self.pending[idx] = Some(PendingDecision::Treasure);
+        }
+        diving
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng, rngs::StdRng, seq::IndexedRandom};
+
+    use crate::{
+        deep_sea::DiveDirection,
+        deep_sea_vectorization::{ActionMask, DeepSeaAction, DeepSeaState, EncoderConfig},
+        error::DeepSeaError,
+        ml::{
+            batched_env::BatchedDeepSeaEnv,
+            env::{DeepSeaEnv, PendingDecision},
+            ppo::{observation, observation_size},
+        },
+        solver::TreasureDecision,
+    };
+
+    #[test]
+    fn test_matches_env() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for num_players in 2..=6 {
+            let seed = num_players as u64;
+            let mut batched = BatchedDeepSeaEnv::new(8, num_players, seed).unwrap();
+            let mut seeds = StdRng::seed_from_u64(seed);
+            let mut envs: Vec<_> = (0..8)
+                .map(|_| DeepSeaEnv::with_seed(num_players, seeds.random()).unwrap())
+                .collect();
+
+            while !batched.done() {
+                let observations = batched
+                    .observations::<f32>(EncoderConfig::padded())
+                    .to_vec::<f32>()
+                    .unwrap();
+                let rows = observations.chunks(observation_size());
+                let mut actions = vec![];
+                for ((idx, env), row) in envs.iter().enumerate().zip(rows) {
+                    assert_eq!(batched.pending()[idx], env.pending());
+                    let mut state = DeepSeaState::from(env.state());
+                    state.forget_history(EncoderConfig::default());
+                    assert_eq!(batched.state(idx), state);
+                    assert_eq!(
+                        batched.action_mask(idx),
+                        ActionMask::from_actions(env.legal_actions())
+                    );
+                    if env.pending().is_some() {
+                        assert_eq!(row, observation(env.state()));
+                    } else {
+                        assert!(row.iter().all(|&x| x == 0.));
+                    }
+                    actions.push(env.legal_actions().choose(&mut rng).copied());
+                }
+
+                batched.step(&actions).unwrap();
+                for (env, action) in envs.iter_mut().zip(&actions) {
+                    if let Some(action) = action {
+                        env.step(*action).unwrap();
+                    }
+                }
+            }
+            for (idx, env) in envs.iter_mut().enumerate() {
+                assert_eq!(batched.scores(idx), env.scores());
+            }
+        }
+    }
+
+    #[test]
+    fn test_step_validates_actions() {
+        let mut batched = BatchedDeepSeaEnv::new(2, 3, 0).unwrap();
+        let down = Some(DeepSeaAction::DiveDirection(DiveDirection::Down));
+        assert!(matches!(
+            batched.step(&[down, None]),
+            Err(DeepSeaError::UnexpectedAction {
+                env_idx: Some(1),
+                pending: Some(PendingDecision::Direction),
+                action: None,
+            })
+        ));
+        let ignore = Some(DeepSeaAction::TreasureDecision(TreasureDecision::Ignore));
+        assert!(matches!(
+            batched.step(&[ignore, down]),
+            Err(DeepSeaError::UnexpectedAction {
+                env_idx: Some(0),
+                pending: Some(PendingDecision::Direction),
+                action: Some(_),
+            })
+        ));
+        batched.step(&[down, down]).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected one action per game")]
+    fn test_step_needs_an_action_per_game() {
+        let mut batched = BatchedDeepSeaEnv::new(2, 3, 0).unwrap();
+        let _ = batched.step(&[Some(DeepSeaAction::DiveDirection(DiveDirection::Down))]);
+    }
+}
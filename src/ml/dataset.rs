@@ -0,0 +1,343 @@
+use std::{
+    cell::RefCell,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+    rc::Rc,
+};
+
+use npyz::{AutoSerialize, WriterBuilder, npz::NpzWriter};
+
+use crate::{
+    deep_sea::{DeepSea, DiveDirection},
+    deep_sea_vectorization::{
+        ActionMask, DEEP_SEA_ACTION_COUNT, DeepSeaAction, DeepSeaState, EncoderConfig,
+    },
+    engine::Engine,
+    error::DeepSeaResult,
+    ml::{
+        ppo::{observation, observation_size},
+        vectorization::{Describable, Feature, FeatureKind},
+        win_shares,
+    },
+    solver::{DeepSeaSolver, TreasureDecision},
+};
+
+/// Name of the file describing every column of a saved dataset.
+pub const COLUMNS_FILE: &str = "columns.tsv";
+/// Name of the archive holding every array of a dataset saved as `DatasetFormat::Npz`.
+pub const NPZ_FILE: &str = "dataset.npz";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DatasetFormat {
+    /// One `<array>.npy` file per array.
+    Npy,
+    /// Every array in a single `dataset.npz` archive.
+    Npz,
+}
+
+/// One decision made during a recorded game.
+struct Decision {
+    player_idx: usize,
+    observation: Vec<f32>,
+    action: DeepSeaAction,
+    legal_actions: ActionMask,
+}
+
+/// Passes decisions through to `solver`, logging each of them.
+struct RecordingSolver {
+    solver: Box<dyn DeepSeaSolver>,
+    log: Rc<RefCell<Vec<Decision>>>,
+}
+
+impl RecordingSolver {
+    fn record(&self, deep_sea: &DeepSea, action: DeepSeaAction, legal_actions: ActionMask) {
+        self.log.borrow_mut().push(Decision {
+            player_idx: deep_sea.player_idx(),
+            observation: observation(deep_sea),
+            action,
+            legal_actions,
+        });
+    }
+}
+
+impl DeepSeaSolver for RecordingSolver {
+    fn choose_direction(&mut self, deep_sea: &DeepSea, player_idx: usize) -> DiveDirection {
+        let direction = self.solver.choose_direction(deep_sea, player_idx);
+        self.record(deep_sea, direction.into(), ActionMask::directions(deep_sea));
+        direction
+    }
+
+    fn take_treasure(&mut self, deep_sea: &DeepSea, player_idx: usize) -> TreasureDecision {
+        let decision = self.solver.take_treasure(deep_sea, player_idx);
+        let legal_actions = ActionMask::treasure_decisions(deep_sea);
+        self.record(deep_sea, decision.into(), legal_actions);
+        decision
+    }
+}
+
+/// Every decision of a number of recorded games, one row per decision. Observations are the
+/// padded features models are fed, so rows from games of any size line up.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Dataset {
+    /// `[rows, observation_size()]`.
+    observations: Vec<f32>,
+    /// `DeepSeaAction::index` of the chosen action.
+    actions: Vec<i64>,
+    /// Share of the win the deciding player ended the game with.
+    rewards: Vec<f32>,
+    /// `[rows, DEEP_SEA_ACTION_COUNT]`, set for each action that was legal.
+    legal_actions: Vec<bool>,
+    /// Index of the game a decision was made in.
+    games: Vec<i64>,
+}
+
+impl Dataset {
+    /// Plays `num_games` games, each between the solvers `make_solvers` returns, and records
+    /// every decision made.
+    pub fn generate(
+        num_games: usize,
+        mut make_solvers: impl FnMut() -> DeepSeaResult<Vec<Box<dyn DeepSeaSolver>>>,
+    ) -> DeepSeaResult<Self> {
+        let mut dataset = Self::default();
+        for game in 0..num_games {
+            let log = Rc::new(RefCell::new(vec![]));
+            let solvers = make_solvers()?
+                .into_iter()
+                .map(|solver| {
+                    Box::new(RecordingSolver {
+                        solver,
+                        log: log.clone(),
+                    }) as Box<dyn DeepSeaSolver>
+                })
+                .collect();
+            let rewards = win_shares(&Engine::make_default_game(solvers).play_one_round()?);
+
+            for decision in log.take() {
+                dataset.observations.extend(decision.observation);
+                dataset.actions.push(decision.action.index() as i64);
+                dataset.rewards.push(rewards[decision.player_idx]);
+                dataset
+                    .legal_actions
+                    .extend(decision.legal_actions.as_slice());
+                dataset.games.push(game as i64);
+            }
+        }
+        Ok(dataset)
+    }
+
+    pub fn len(&self) -> usize {
+        self.actions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    /// Writes the arrays in `format` along with `columns.tsv` into the directory `dir`.
+    pub fn save(&self, dir: impl AsRef<Path>, format: DatasetFormat) -> DeepSeaResult {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        fs::write(dir.join(COLUMNS_FILE), Self::columns())?;
+
+        let rows = self.len() as u64;
+        let observations_shape = [rows, observation_size() as u64];
+        let legal_actions_shape = [rows, DEEP_SEA_ACTION_COUNT as u64];
+        match format {
+            DatasetFormat::Npy => {
+                let file = |name: &str| -> io::Result<_> {
+                    Ok(BufWriter::new(File::create(
+                        dir.join(format!("{name}.npy")),
+                    )?))
+                };
+                write_npy(
+                    file("observations")?,
+                    &self.observations,
+                    &observations_shape,
+                )?;
+                write_npy(file("actions")?, &self.actions, &[rows])?;
+                write_npy(file("rewards")?, &self.rewards, &[rows])?;
+                write_npy(
+                    file("legal_actions")?,
+                    &self.legal_actions,
+                    &legal_actions_shape,
+                )?;
+                write_npy(file("games")?, &self.games, &[rows])?;
+            }
+            DatasetFormat::Npz => {
+                let mut npz = NpzWriter::create(dir.join(NPZ_FILE))?;
+                write_npz(
+                    &mut npz,
+                    "observations",
+                    &self.observations,
+                    &observations_shape,
+                )?;
+                write_npz(&mut npz, "actions", &self.actions, &[rows])?;
+                write_npz(&mut npz, "rewards", &self.rewards, &[rows])?;
+                write_npz(
+                    &mut npz,
+                    "legal_actions",
+                    &self.legal_actions,
+                    &legal_actions_shape,
+                )?;
+                write_npz(&mut npz, "games", &self.games, &[rows])?;
+                npz.zip_writer()
+                    .finish()
+                    .map_err(|e| io::Error::other(format!("Cannot write {NPZ_FILE}: {e}")))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Tab-separated `array, column, name, kind` lines describing each column of each array.
+    pub fn columns() -> String {
+        let mut observations = vec![];
+        DeepSeaState::default().describe_into("", EncoderConfig::padded(), &mut observations);
+        let mut legal_actions = vec![];
+        ActionMask::default().describe_into("action", EncoderConfig::padded(), &mut legal_actions);
+        let arrays = [
+            ("observations", observations),
+            (
+                "actions",
+                vec![Feature::new("action index", FeatureKind::Integer)],
+            ),
+            (
+                "rewards",
+                vec![Feature::new("win share", FeatureKind::Fraction)],
+            ),
+            ("legal_actions", legal_actions),
+            ("games", vec![Feature::new("game", FeatureKind::Integer)]),
+        ];
+
+        let mut columns = "array\tcolumn\tname\tkind\n".to_owned();
+        for (array, features) in arrays {
+            for (idx, feature) in features.iter().enumerate() {
+                columns += &format!("{array}\t{idx}\t{}\t{:?}\n", feature.name, feature.kind);
+            }
+        }
+        columns
+    }
+}
+
+fn write_npy<T: AutoSerialize + Copy>(
+    writer: impl Write,
+    data: &[T],
+    shape: &[u64],
+) -> io::Result<()> {
+    let mut npy = npyz::WriteOptions::new()
+        .default_dtype()
+        .shape(shape)
+        .writer(writer)
+        .begin_nd()?;
+    npy.extend(data.iter().copied())?;
+    npy.finish()
+}
+
+fn write_npz<T: AutoSerialize + Copy, W: Write + io::Seek>(
+    npz: &mut NpzWriter<W>,
+    name: &str,
+    data: &[T],
+    shape: &[u64],
+) -> io::Result<()> {
+    let mut npy = npz
+        .array::<T>(name, Default::default())?
+        .default_dtype()
+        .shape(shape)
+        .begin_nd()?;
+    npy.extend(data.iter().copied())?;
+    npy.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+
+    use itertools::Itertools;
+    use npyz::{NpyFile, npz::NpzArchive};
+
+    use crate::{
+        deep_sea_vectorization::DEEP_SEA_ACTION_COUNT,
+        ml::{
+            dataset::{COLUMNS_FILE, Dataset, DatasetFormat, NPZ_FILE},
+            ppo::observation_size,
+        },
+        random_solver::RandomSolver,
+        solver::DeepSeaSolver,
+    };
+
+    fn random_games(num_games: usize, num_players: usize) -> Dataset {
+        Dataset::generate(num_games, || {
+            Ok((0..num_players)
+                .map(|_| Box::new(RandomSolver) as Box<dyn DeepSeaSolver>)
+                .collect())
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_generate() {
+        let dataset = random_games(3, 4);
+        let rows = dataset.len();
+        assert!(rows > 0);
+        assert_eq!(dataset.observations.len(), rows * observation_size());
+        assert_eq!(dataset.legal_actions.len(), rows * DEEP_SEA_ACTION_COUNT);
+        assert_eq!(dataset.games.first(), Some(&0));
+        assert_eq!(dataset.games.last(), Some(&2));
+        assert!(dataset.rewards.iter().all(|r| (0. ..=1.).contains(r)));
+        // Every decision has a legal alternative.
+        for mask in dataset.legal_actions.chunks(DEEP_SEA_ACTION_COUNT) {
+            assert!(mask.iter().any(|&legal| legal));
+        }
+    }
+
+    #[test]
+    fn test_save() {
+        let dataset = random_games(2, 3);
+        let rows = dataset.len() as u64;
+        let tempdir = tempfile::tempdir().unwrap();
+
+        let dir = tempdir.path().join("npy");
+        dataset.save(&dir, DatasetFormat::Npy).unwrap();
+        let observations = NpyFile::new(File::open(dir.join("observations.npy")).unwrap()).unwrap();
+        assert_eq!(observations.shape(), &[rows, observation_size() as u64]);
+        assert_eq!(
+            observations.into_vec::<f32>().unwrap(),
+            dataset.observations
+        );
+        let legal_actions =
+            NpyFile::new(File::open(dir.join("legal_actions.npy")).unwrap()).unwrap();
+        assert_eq!(
+            legal_actions.into_vec::<bool>().unwrap(),
+            dataset.legal_actions
+        );
+
+        let columns = fs::read_to_string(dir.join(COLUMNS_FILE)).unwrap();
+        assert_eq!(
+            columns
+                .lines()
+                .filter(|line| line.starts_with("observations\t"))
+                .count(),
+            observation_size()
+        );
+        assert!(columns.contains("legal_actions\t7\taction=Up\tFlag\n"));
+
+        let dir = tempdir.path().join("npz");
+        dataset.save(&dir, DatasetFormat::Npz).unwrap();
+        let mut npz = NpzArchive::open(dir.join(NPZ_FILE)).unwrap();
+        assert_eq!(
+            npz.array_names().sorted().collect_vec(),
+            vec![
+                "actions",
+                "games",
+                "legal_actions",
+                "observations",
+                "rewards"
+            ]
+        );
+        let actions = npz.by_name("actions").unwrap().unwrap();
+        assert_eq!(actions.shape(), &[rows]);
+        assert_eq!(actions.into_vec::<i64>().unwrap(), dataset.actions);
+
+        tempdir.close().unwrap();
+    }
+}
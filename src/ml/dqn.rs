@@ -0,0 +1,460 @@
+use std::{cell::RefCell, rc::Rc};
+
+use burn::{
+    module::AutodiffModule,
+    nn::{Linear, LinearConfig, Relu},
+    optim::{AdamConfig, GradientsParams, Optimizer},
+    prelude::*,
+    tensor::backend::AutodiffBackend,
+};
+use itertools::Itertools;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+use crate::{
+    chance::RngChance,
+    deep_sea::{DeepSea, DiveDirection},
+    deep_sea_vectorization::{
+        DEEP_SEA_ACTION_COUNT, DeepSeaAction, DeepSeaState, DeepSeaStateActionPair, EncoderConfig,
+        Perspective,
+    },
+    engine::Engine,
+    error::DeepSeaResult,
+    ml::{
+        CpuBackend, batch_tensor,
+        neural_solver::{NeuralSolver, PolicyModel},
+        replay::{ReplayBatch, ReplayBuffer, Sampling, Transition},
+        vectorization::{ToBatch, Unpackable},
+        win_shares,
+    },
+    solver::{DeepSeaSolver, TreasureDecision},
+};
+
+#[derive(Config, Debug)]
+pub struct QNetworkConfig {
+    /// Width of a vectorized `DeepSeaStateActionPair`.
+    pub input_size: usize,
+    #[config(default = 128)]
+    pub hidden_size: usize,
+}
+
+impl QNetworkConfig {
+    pub fn init<B: Backend>(&self, device: &B::Device) -> QNetwork<B> {
+        QNetwork {
+            input: LinearConfig::new(self.input_size, self.hidden_size).init(device),
+            hidden: LinearConfig::new(self.hidden_size, self.hidden_size).init(device),
+            output: LinearConfig::new(self.hidden_size, 1).init(device),
+            activation: Relu::new(),
+        }
+    }
+}
+
+/// Scores a vectorized state-action pair with the expected win share of taking that action.
+#[derive(Module, Debug)]
+pub struct QNetwork<B: Backend> {
+    input: Linear<B>,
+    hidden: Linear<B>,
+    output: Linear<B>,
+    activation: Relu,
+}
+
+impl<B: Backend> QNetwork<B> {
+    /// Maps a `[batch, input_size]` tensor to `[batch, 1]` Q-values.
+    pub fn forward(&self, input: Tensor<B, 2>) -> Tensor<B, 2> {
+        let x = self.activation.forward(self.input.forward(input));
+        let x = self.activation.forward(self.hidden.forward(x));
+        self.output.forward(x)
+    }
+
+    /// Q-values of each row, in order.
+    fn q_values(&self, rows: &[Vec<f32>], device: &B::Device) -> Vec<f32> {
+        if rows.is_empty() {
+            return vec![];
+        }
+        self.forward(batch_tensor(rows, device))
+            .into_data()
+            .convert::<f32>()
+            .to_vec()
+            .unwrap()
+    }
+}
+
+/// Vectorizes the current state of `deep_sea` paired with each of `actions`.
+fn candidate_vectors(
+    deep_sea: &DeepSea,
+    actions: impl IntoIterator<Item = DeepSeaAction>,
+) -> Vec<Vec<f32>> {
+    let state = Perspective::acting(deep_sea).observe(deep_sea);
+    actions
+        .into_iter()
+        .map(|action| {
+            DeepSeaStateActionPair {
+                state: &state,
+                action: &action,
+            }
+            .unpack_with::<f32>(EncoderConfig::padded())
+            .collect()
+        })
+        .collect()
+}
+
+/// Width of a vectorized state-action pair, whatever the game.
+pub fn state_action_size() -> usize {
+    DeepSeaState::default().unpacked_size_with(EncoderConfig::padded()) + DEEP_SEA_ACTION_COUNT
+}
+
+impl<B: Backend> PolicyModel<B> for QNetwork<B> {
+    const NAME: &'static str = "QNetwork";
+
+    type Config = QNetworkConfig;
+
+    fn init(config: &QNetworkConfig, device: &B::Device) -> Self {
+        config.init(device)
+    }
+
+    fn input_size() -> usize {
+        state_action_size()
+    }
+
+    fn score_actions(
+        &self,
+        deep_sea: &DeepSea,
+        actions: &[DeepSeaAction],
+        device: &B::Device,
+    ) -> Vec<f32> {
+        if actions.is_empty() {
+            return vec![];
+        }
+        let state = Perspective::acting(deep_sea).observe(deep_sea);
+        let pairs = actions
+            .iter()
+            .map(|action| DeepSeaStateActionPair {
+                state: &state,
+                action,
+            })
+            .collect_vec();
+        self.forward(Tensor::from_data(
+            pairs.to_batch_tensordata_with::<f32>(EncoderConfig::padded()),
+            device,
+        ))
+        .into_data()
+        .convert::<f32>()
+        .to_vec()
+        .unwrap()
+    }
+}
+
+/// Plays the legal action with the highest Q-value.
+pub type DqnSolver<B = CpuBackend> = NeuralSolver<QNetwork<B>, B>;
+
+/// One decision made during self-play, with every alternative that was available.
+struct Decision {
+    player_idx: usize,
+    chosen: usize,
+    candidates: Vec<Vec<f32>>,
+}
+
+/// Epsilon-greedy solver which logs its decisions for the replay buffer.
+struct ExploringSolver<B: Backend> {
+    model: QNetwork<B>,
+    device: B::Device,
+    epsilon: f64,
+    rng: StdRng,
+    log: Rc<RefCell<Vec<Decision>>>,
+}
+
+impl<B: Backend> ExploringSolver<B> {
+    fn decide(&mut self, player_idx: usize, candidates: Vec<Vec<f32>>) -> usize {
+        let chosen = if self.rng.random_bool(self.epsilon) {
+            self.rng.random_range(0..candidates.len())
+        } else {
+            self.model
+                .q_values(&candidates, &self.device)
+                .into_iter()
+                .position_max_by(f32::total_cmp)
+                .unwrap()
+        };
+        self.log.borrow_mut().push(Decision {
+            player_idx,
+            chosen,
+            candidates,
+        });
+        chosen
+    }
+}
+
+impl<B: Backend> DeepSeaSolver for ExploringSolver<B> {
+    fn choose_direction(&mut self, deep_sea: &DeepSea, player_idx: usize) -> DiveDirection {
+        let directions = deep_sea.legal_directions();
+        let candidates = candidate_vectors(deep_sea, directions.iter().map(|&d| d.into()));
+        directions[self.decide(player_idx, candidates)]
+    }
+
+    fn take_treasure(&mut self, deep_sea: &DeepSea, player_idx: usize) -> TreasureDecision {
+        let decisions = deep_sea.legal_treasure_decisions();
+        let candidates = candidate_vectors(deep_sea, decisions.iter().map(|&d| d.into()));
+        decisions[self.decide(player_idx, candidates)]
+    }
+}
+
+/// Turns one game's decisions into transitions between each player's consecutive decisions,
+/// rewarding only the last one with that player's win share.
+fn episode_transitions(decisions: Vec<Decision>, scores: &[u32]) -> Vec<Transition> {
+    let rewards = win_shares(scores);
+    decisions
+        .into_iter()
+        .into_group_map_by(|decision| decision.player_idx)
+        .into_iter()
+        .sorted_by_key(|&(player_idx, _)| player_idx)
+        .flat_map(|(player_idx, decisions)| {
+            let mut decisions = decisions.into_iter().peekable();
+            let mut transitions = vec![];
+            while let Some(mut decision) = decisions.next() {
+                let state_action = decision.candidates.swap_remove(decision.chosen);
+                transitions.push(match decisions.peek() {
+                    Some(next) => Transition {
+                        state_action,
+                        reward: 0.,
+                        next_candidates: next.candidates.clone(),
+                    },
+                    None => Transition {
+                        state_action,
+                        reward: rewards[player_idx],
+                        next_candidates: vec![],
+                    },
+                });
+            }
+            transitions
+        })
+        .collect()
+}
+
+#[derive(Config, Debug)]
+pub struct DqnConfig {
+    #[config(default = 6)]
+    pub num_players: usize,
+    #[config(default = 128)]
+    pub hidden_size: usize,
+    #[config(default = 1000)]
+    pub num_episodes: usize,
+    #[config(default = 20000)]
+    pub replay_capacity: usize,
+    /// Replays transitions in proportion to their last temporal-difference error.
+    #[config(default = false)]
+    pub prioritized_replay: bool,
+    #[config(default = 0.6)]
+    pub priority_alpha: f32,
+    #[config(default = 0.4)]
+    pub priority_beta: f32,
+    #[config(default = 64)]
+    pub batch_size: usize,
+    #[config(default = 4)]
+    pub updates_per_episode: usize,
+    /// Episodes between copies of the online network into the target network.
+    #[config(default = 10)]
+    pub target_sync_interval: usize,
+    #[config(default = 1.0)]
+    pub gamma: f32,
+    #[config(default = 1e-3)]
+    pub learning_rate: f64,
+    #[config(default = 1.0)]
+    pub epsilon_start: f64,
+    #[config(default = 0.05)]
+    pub epsilon_end: f64,
+    #[config(default = 0)]
+    pub seed: u64,
+}
+
+impl DqnConfig {
+    pub fn network(&self) -> QNetworkConfig {
+        QNetworkConfig::new(state_action_size()).with_hidden_size(self.hidden_size)
+    }
+
+    fn sampling(&self) -> Sampling {
+        if self.prioritized_replay {
+            Sampling::Prioritized {
+                alpha: self.priority_alpha,
+                beta: self.priority_beta,
+            }
+        } else {
+            Sampling::Uniform
+        }
+    }
+
+    /// Linearly anneals epsilon over the course of training.
+    fn epsilon(&self, episode: usize) -> f64 {
+        let progress = episode as f64 / self.num_episodes.max(1) as f64;
+        self.epsilon_start + (self.epsilon_end - self.epsilon_start) * progress
+    }
+}
+
+/// Trains a Q-network through self-play, every seat being played by the network being trained.
+pub fn train<B: AutodiffBackend>(
+    config: &DqnConfig,
+    device: &B::Device,
+) -> DeepSeaResult<QNetwork<B>> {
+    B::seed(config.seed);
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let mut model: QNetwork<B> = config.network().init(device);
+    let mut target = model.valid();
+    let mut optim = AdamConfig::new().init();
+    let mut buffer = ReplayBuffer::new(config.replay_capacity, config.sampling(), rng.random());
+
+    for episode in 0..config.num_episodes {
+        let log = Rc::new(RefCell::new(vec![]));
+        let solvers = (0..config.num_players)
+            .map(|_| {
+                Box::new(ExploringSolver {
+                    model: model.valid(),
+                    device: device.clone(),
+                    epsilon: config.epsilon(episode),
+                    rng: StdRng::seed_from_u64(rng.random()),
+                    log: log.clone(),
+                }) as Box<dyn DeepSeaSolver>
+            })
+            .collect();
+        let scores = Engine::make_default_game(solvers)
+            .with_chance(RngChance::seeded(rng.random()))
+            .play_one_round()?;
+        for transition in episode_transitions(log.take(), &scores) {
+            buffer.push(transition);
+        }
+
+        if buffer.len() >= config.batch_size {
+            for _ in 0..config.updates_per_episode {
+                let batch = buffer.sample(config.batch_size);
+                let td_errors;
+                (model, td_errors) = optimize(model, &target, &mut optim, &batch, config, device);
+                // The batch borrows the buffer, so release it before updating priorities.
+                let indices = batch.indices;
+                buffer.update_priorities(&indices, &td_errors);
+            }
+        }
+
+        if (episode + 1) % config.target_sync_interval == 0 {
+            target = model.valid();
+        }
+    }
+
+    Ok(model)
+}
+
+fn optimize<B: AutodiffBackend, O: Optimizer<QNetwork<B>, B>>(
+    model: QNetwork<B>,
+    target: &QNetwork<B::InnerBackend>,
+    optim: &mut O,
+    batch: &ReplayBatch,
+    config: &DqnConfig,
+    device: &B::Device,
+) -> (QNetwork<B>, Vec<f32>) {
+    let next_q = target.q_values(
+        &batch
+            .transitions
+            .iter()
+            .flat_map(|transition| transition.next_candidates.iter().cloned())
+            .collect_vec(),
+        device,
+    );
+    let mut next_q = next_q.into_iter();
+    let targets = batch
+        .transitions
+        .iter()
+        .map(|transition| {
+            let next_max = next_q
+                .by_ref()
+                .take(transition.next_candidates.len())
+                .max_by(f32::total_cmp);
+            transition.reward + config.gamma * next_max.unwrap_or_default()
+        })
+        .collect_vec();
+
+    let q = model.forward(batch.state_actions(device));
+    let targets = Tensor::from_data(TensorData::new(targets, [batch.len(), 1]), device);
+    let errors = targets - q;
+    let td_errors = errors
+        .clone()
+        .into_data()
+        .convert::<f32>()
+        .to_vec()
+        .unwrap();
+    // Importance weights undo the bias of prioritized sampling and are all 1 otherwise.
+    let loss = (errors.powi_scalar(2) * batch.weights(device)).mean();
+
+    let grads = GradientsParams::from_grads(loss.backward(), &model);
+    (optim.step(config.learning_rate, model, grads), td_errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use burn::module::AutodiffModule;
+
+    use crate::{
+        engine::Engine,
+        ml::{
+            TrainingBackend,
+            dqn::{Decision, DqnConfig, DqnSolver, episode_transitions, state_action_size, train},
+        },
+        solver::DeepSeaSolver,
+    };
+
+    #[test]
+    fn test_episode_transitions() {
+        let decision = |player_idx, value: f32| Decision {
+            player_idx,
+            chosen: 1,
+            candidates: vec![vec![0.], vec![value]],
+        };
+        let transitions = episode_transitions(
+            vec![decision(0, 1.), decision(1, 2.), decision(0, 3.)],
+            &[5, 0],
+        );
+        assert_eq!(transitions.len(), 3);
+
+        let first = transitions
+            .iter()
+            .find(|t| t.state_action == vec![1.])
+            .unwrap();
+        assert_eq!(first.reward, 0.);
+        assert_eq!(first.next_candidates, vec![vec![0.], vec![3.]]);
+
+        let last = transitions
+            .iter()
+            .find(|t| t.state_action == vec![3.])
+            .unwrap();
+        assert_eq!(last.reward, 1.);
+        assert!(last.next_candidates.is_empty());
+
+        let loser = transitions
+            .iter()
+            .find(|t| t.state_action == vec![2.])
+            .unwrap();
+        assert_eq!(loser.reward, 0.);
+        assert!(loser.next_candidates.is_empty());
+    }
+
+    #[test]
+    fn test_state_action_size() {
+        // Tiles, occupancy and presence of 32 path slots, 15 values per player slot including
+        // its presence flag, oxygen, 6 acting-player slots and 8 action slots.
+        assert_eq!(state_action_size(), 3 * 32 + 6 * 15 + 1 + 6 + 8);
+    }
+
+    #[test]
+    fn test_train_and_play() {
+        let config = DqnConfig::new()
+            .with_num_players(3)
+            .with_hidden_size(8)
+            .with_num_episodes(4)
+            .with_batch_size(8)
+            .with_updates_per_episode(2)
+            .with_target_sync_interval(2);
+        let device = Default::default();
+        train::<TrainingBackend>(&config.clone().with_prioritized_replay(true), &device).unwrap();
+        let model = train::<TrainingBackend>(&config, &device).unwrap();
+
+        let solvers = (0..3)
+            .map(|_| Box::new(DqnSolver::new(model.valid(), device)) as Box<dyn DeepSeaSolver>)
+            .collect();
+        let scores = Engine::make_default_game(solvers).play_one_round().unwrap();
+        assert_eq!(scores.len(), 3);
+    }
+}
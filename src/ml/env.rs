@@ -0,0 +1,293 @@
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+use crate::{
+    chance::RngChance,
+    deep_sea::{DeepSea, DiveDirection, Position},
+    deep_sea_vectorization::{ActionMask, DeepSeaAction},
+    engine::Engine,
+    error::{DeepSeaError, DeepSeaResult},
+};
+
+/// The kind of decision the current player owes the environment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PendingDecision {
+    Direction,
+    Treasure,
+}
+
+/// A single game that is advanced one decision at a time instead of through solver callbacks.
+/// Follows the same turn order as `Engine::take_turn`.
+#[derive(Clone, Debug)]
+pub struct DeepSeaEnv {
+    state: DeepSea,
+    pending: Option<PendingDecision>,
+    /// Rolls the dice and reveals chip values.
+    rng: StdRng,
+    /// Revealed once, when the game ends.
+    scores: Option<Vec<u32>>,
+}
+
+impl DeepSeaEnv {
+    pub fn new(num_players: usize) -> DeepSeaResult<Self> {
+        Self::with_rng(num_players, StdRng::from_rng(&mut rand::rng()))
+    }
+
+    /// A game whose dice rolls and final scores are reproducible from `seed`.
+    pub fn with_seed(num_players: usize, seed: u64) -> DeepSeaResult<Self> {
+        Self::with_rng(num_players, StdRng::seed_from_u64(seed))
+    }
+
+    fn with_rng(num_players: usize, rng: StdRng) -> DeepSeaResult<Self> {
+        let mut env = Self {
+            state: DeepSea::new(Engine::default_path(), num_players),
+            pending: None,
+            rng,
+            scores: None,
+        };
+        env.advance()?;
+        Ok(env)
+    }
+
+    pub fn state(&self) -> &DeepSea {
+        &self.state
+    }
+
+    /// The decision the current player has to make, or `None` once the game is over.
+    pub fn pending(&self) -> Option<PendingDecision> {
+        self.pending
+    }
+
+    pub fn legal_actions(&self) -> Vec<DeepSeaAction> {
+        match self.pending {
+            Some(PendingDecision::Direction) => self
+                .state
+                .legal_directions()
+                .into_iter()
+                .map(DeepSeaAction::from)
+                .collect(),
+            Some(PendingDecision::Treasure) => self
+                .state
+                .legal_treasure_decisions()
+                .into_iter()
+                .map(DeepSeaAction::from)
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    /// `legal_actions` as a mask over action slots.
+    pub fn action_mask(&self) -> ActionMask {
+        match self.pending {
+            Some(PendingDecision::Direction) => ActionMask::directions(&self.state),
+            Some(PendingDecision::Treasure) => ActionMask::treasure_decisions(&self.state),
+            None => ActionMask::default(),
+        }
+    }
+
+    pub fn step(&mut self, action: DeepSeaAction) -> DeepSeaResult {
+        match (self.pending, action) {
+            (Some(PendingDecision::Direction), DeepSeaAction::DiveDirection(direction)) => {
+                let dice_roll = Engine::roll_dice_with(&mut self.rng);
+                self.state.move_player(direction, dice_roll)?;
+                if self.await_treasure() {
+                    return Ok(());
+                }
+            }
+            (Some(PendingDecision::Treasure), DeepSeaAction::TreasureDecision(decision)) => {
+                self.state.take_treasure(decision)?;
+            }
+            (pending, action) => {
+                return Err(DeepSeaError::UnexpectedAction {
+                    env_idx: None,
+                    pending,
+                    action: Some(action),
+                });
+            }
+        }
+        self.state.next_player();
+        self.advance()
+    }
+
+    /// Final scores, once the game is over.
+    pub fn scores(&self) -> &[u32] {
+        self.scores.as_deref().expect("The game is not over")
+    }
+
+    /// Plays out turns which need no decision until a player owes one or the game ends.
+    fn advance(&mut self) -> DeepSeaResult {
+        while !self.state.done() {
+            let player = &self.state.players()[self.state.player_idx()];
+            let (position, direction) = (player.position(), player.direction());
+            if position == Position::ReturnedToSubmarine {
+                self.state.next_player();
+                continue;
+            }
+
+            self.state.take_oxygen();
+            if direction == DiveDirection::Down {
+                self.pending = Some(PendingDecision::Direction);
+                return Ok(());
+            }
+
+            let dice_roll = Engine::roll_dice_with(&mut self.rng);
+            self.state.move_player(DiveDirection::Up, dice_roll)?;
+            if self.await_treasure() {
+                return Ok(());
+            }
+            self.state.next_player();
+        }
+        self.pending = None;
+        self.scores = Some(Engine::score_with(
+            &self.state,
+            &mut RngChance::new(&mut self.rng),
+        ));
+        Ok(())
+    }
+
+    /// After moving, a player still diving is asked for a treasure decision.
+    fn await_treasure(&mut self) -> bool {
+        let player = &self.state.players()[self.state.player_idx()];
+        let diving = matches!(player.position(), Position::Diving(_));
+        if diving {
+            self.pending = Some(PendingDecision::Treasure);
+        }
+        diving
+    }
+}
+
+/// Independent games stepped together so their decisions can be batched.
+pub struct VecDeepSeaEnv {
+    envs: Vec<DeepSeaEnv>,
+}
+
+impl VecDeepSeaEnv {
+    pub fn new(num_envs: usize, num_players: usize) -> DeepSeaResult<Self> {
+        Ok(Self {
+            envs: (0..num_envs)
+                .map(|_| DeepSeaEnv::new(num_players))
+                .collect::<DeepSeaResult<_>>()?,
+        })
+    }
+
+    /// Games seeded from `rng`, so that they are reproducible along with it.
+    pub fn with_rng(
+        num_envs: usize,
+        num_players: usize,
+        rng: &mut impl Rng,
+    ) -> DeepSeaResult<Self> {
+        Ok(Self {
+            envs: (0..num_envs)
+                .map(|_| DeepSeaEnv::with_rng(num_players, StdRng::from_rng(&mut *rng)))
+                .collect::<DeepSeaResult<_>>()?,
+        })
+    }
+
+    pub fn envs(&self) -> &[DeepSeaEnv] {
+        &self.envs
+    }
+
+    /// Indices of the games still waiting on a decision.
+    pub fn active(&self) -> Vec<usize> {
+        (0..self.envs.len())
+            .filter(|&idx| self.envs[idx].pending().is_some())
+            .collect()
+    }
+
+    pub fn done(&self) -> bool {
+        self.envs.iter().all(|env| env.pending().is_none())
+    }
+
+    /// Applies one action to each of the given games.
+    pub fn step(&mut self, actions: &[(usize, DeepSeaAction)]) -> DeepSeaResult {
+        for &(idx, action) in actions {
+            self.envs[idx].step(action)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::seq::IndexedRandom;
+
+    use crate::{
+        deep_sea::{DiveDirection, Position},
+        deep_sea_vectorization::DeepSeaAction,
+        error::DeepSeaError,
+        ml::env::{DeepSeaEnv, PendingDecision, VecDeepSeaEnv},
+        solver::TreasureDecision,
+    };
+
+    #[test]
+    fn test_first_decision() {
+        let env = DeepSeaEnv::new(3).unwrap();
+        assert_eq!(env.pending(), Some(PendingDecision::Direction));
+        assert_eq!(env.state().player_idx(), 0);
+        assert_eq!(
+            env.legal_actions(),
+            vec![DeepSeaAction::DiveDirection(DiveDirection::Down)]
+        );
+    }
+
+    #[test]
+    fn test_step_rejects_mismatched_action() {
+        let mut env = DeepSeaEnv::new(2).unwrap();
+        assert!(matches!(
+            env.step(DeepSeaAction::TreasureDecision(TreasureDecision::Take)),
+            Err(DeepSeaError::UnexpectedAction {
+                env_idx: None,
+                pending: Some(PendingDecision::Direction),
+                action: Some(DeepSeaAction::TreasureDecision(TreasureDecision::Take)),
+            })
+        ));
+
+        env.step(DeepSeaAction::DiveDirection(DiveDirection::Down))
+            .unwrap();
+        assert_eq!(env.pending(), Some(PendingDecision::Treasure));
+        assert_eq!(
+            env.action_mask().actions().collect::<Vec<_>>(),
+            env.legal_actions()
+        );
+        assert!(matches!(
+            env.state().players()[0].position(),
+            Position::Diving(_)
+        ));
+    }
+
+    #[test]
+    fn test_random_games_finish() {
+        let mut envs = VecDeepSeaEnv::new(8, 4).unwrap();
+        let mut rng = rand::rng();
+        while !envs.done() {
+            let actions: Vec<_> = envs
+                .active()
+                .into_iter()
+                .map(|idx| {
+                    (
+                        idx,
+                        *envs.envs()[idx].legal_actions().choose(&mut rng).unwrap(),
+                    )
+                })
+                .collect();
+            envs.step(&actions).unwrap();
+        }
+        for env in envs.envs() {
+            assert!(env.state().done());
+            assert_eq!(env.action_mask().actions().count(), 0);
+            assert_eq!(env.scores().len(), 4);
+        }
+    }
+
+    #[test]
+    fn test_seeded_games_reproduce() {
+        let play = |seed| {
+            let mut env = DeepSeaEnv::with_seed(3, seed).unwrap();
+            while env.pending().is_some() {
+                let action = *env.legal_actions().last().unwrap();
+                env.step(action).unwrap();
+            }
+            (env.state().clone(), env.scores().to_vec())
+        };
+        assert_eq!(play(7), play(7));
+    }
+}
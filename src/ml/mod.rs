@@ -1 +1,40 @@
+pub mod batched_env;
+pub mod dataset;
+pub mod dqn;
+pub mod env;
+pub mod neural_solver;
+pub mod ppo;
+pub mod replay;
 pub mod vectorization;
+
+use burn::{
+    backend::{Autodiff, NdArray},
+    prelude::*,
+};
+
+/// CPU backend used for inference.
+pub type CpuBackend = NdArray<f32>;
+/// CPU backend used for training.
+pub type TrainingBackend = Autodiff<CpuBackend>;
+
+/// Splits one win between the highest scoring players, mirroring `Engine::evaluate_solvers`.
+pub(crate) fn win_shares(scores: &[u32]) -> Vec<f32> {
+    let max_score = scores.iter().cloned().max().unwrap_or_default();
+    let highest_players = scores.iter().filter(|&&score| score == max_score).count();
+    scores
+        .iter()
+        .map(|&score| {
+            if score == max_score {
+                1. / highest_players as f32
+            } else {
+                0.
+            }
+        })
+        .collect()
+}
+
+/// Stacks equally sized rows into a `[rows, width]` tensor.
+pub(crate) fn batch_tensor<B: Backend>(rows: &[Vec<f32>], device: &B::Device) -> Tensor<B, 2> {
+    let width = rows[0].len();
+    Tensor::from_data(TensorData::new(rows.concat(), [rows.len(), width]), device)
+}
@@ -0,0 +1,228 @@
+use std::{fs, path::Path};
+
+use burn::{
+    prelude::*,
+    record::{FullPrecisionSettings, NamedMpkFileRecorder},
+};
+use itertools::Itertools;
+
+use crate::{
+    deep_sea::{DeepSea, DiveDirection},
+    deep_sea_vectorization::{DeepSeaAction, ENCODING_VERSION},
+    error::{DeepSeaError, DeepSeaResult},
+    ml::CpuBackend,
+    solver::{DeepSeaSolver, TreasureDecision},
+};
+
+/// Bumped whenever the checkpoint directory layout or `CheckpointMetadata` changes.
+pub const CHECKPOINT_FORMAT_VERSION: u32 = 1;
+
+const METADATA_FILE: &str = "metadata.json";
+const CONFIG_FILE: &str = "config.json";
+const MODEL_FILE: &str = "model";
+
+/// A network which can rank the legal actions of a position.
+pub trait PolicyModel<B: Backend>: Module<B> {
+    /// Identifies the architecture in checkpoint metadata.
+    const NAME: &'static str;
+
+    type Config: Config;
+
+    fn init(config: &Self::Config, device: &B::Device) -> Self;
+
+    /// Width of the vectors this model consumes, which is the same for every game.
+    fn input_size() -> usize;
+
+    /// Scores each of `actions` in `deep_sea`, higher being better.
+    fn score_actions(
+        &self,
+        deep_sea: &DeepSea,
+        actions: &[DeepSeaAction],
+        device: &B::Device,
+    ) -> Vec<f32>;
+}
+
+/// Describes a checkpoint, so that it is only loaded against the feature encoding and model it
+/// was trained with.
+#[derive(Config, Debug)]
+pub struct CheckpointMetadata {
+    pub format_version: u32,
+    /// `ENCODING_VERSION` at training time.
+    pub encoding_version: u32,
+    /// `PolicyModel::NAME` of the saved model.
+    pub model: String,
+    /// Players per game during training.
+    pub num_players: usize,
+    pub input_size: usize,
+}
+
+impl CheckpointMetadata {
+    fn validate<B: Backend, M: PolicyModel<B>>(&self) -> DeepSeaResult {
+        let expected = (
+            CHECKPOINT_FORMAT_VERSION,
+            ENCODING_VERSION,
+            M::NAME,
+            M::input_size(),
+        );
+        let found = (
+            self.format_version,
+            self.encoding_version,
+            self.model.as_str(),
+            self.input_size,
+        );
+        if expected == found {
+            Ok(())
+        } else {
+            Err(DeepSeaError::InvalidConfig(format!(
+                "Checkpoint (format, encoding, model, input size) is {found:?}, expected {expected:?}"
+            )))
+        }
+    }
+}
+
+fn recorder() -> NamedMpkFileRecorder<FullPrecisionSettings> {
+    NamedMpkFileRecorder::new()
+}
+
+/// Writes `model`, its config and a `CheckpointMetadata` into the directory `dir`.
+pub fn save_checkpoint<B: Backend, M: PolicyModel<B>>(
+    dir: impl AsRef<Path>,
+    model: M,
+    config: &M::Config,
+    num_players: usize,
+) -> DeepSeaResult {
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir)?;
+    CheckpointMetadata::new(
+        CHECKPOINT_FORMAT_VERSION,
+        ENCODING_VERSION,
+        M::NAME.to_owned(),
+        num_players,
+        M::input_size(),
+    )
+    .save(dir.join(METADATA_FILE))?;
+    config.save(dir.join(CONFIG_FILE))?;
+    model.save_file(dir.join(MODEL_FILE), &recorder())?;
+    Ok(())
+}
+
+/// Plays the legal action its model scores highest.
+pub struct NeuralSolver<M, B: Backend = CpuBackend> {
+    model: M,
+    device: B::Device,
+}
+
+impl<B: Backend, M: PolicyModel<B>> NeuralSolver<M, B> {
+    pub fn new(model: M, device: B::Device) -> Self {
+        Self { model, device }
+    }
+
+    /// Loads a checkpoint written by `save_checkpoint`, failing if it was trained for a
+    /// different model or feature encoding.
+    pub fn load(dir: impl AsRef<Path>, device: B::Device) -> DeepSeaResult<Self> {
+        let dir = dir.as_ref();
+        CheckpointMetadata::load(dir.join(METADATA_FILE))?.validate::<B, M>()?;
+        let config = M::Config::load(dir.join(CONFIG_FILE))?;
+        let model =
+            M::init(&config, &device).load_file(dir.join(MODEL_FILE), &recorder(), &device)?;
+        Ok(Self::new(model, device))
+    }
+
+    pub fn model(&self) -> &M {
+        &self.model
+    }
+
+    fn choose(&self, deep_sea: &DeepSea, actions: &[DeepSeaAction]) -> usize {
+        self.model
+            .score_actions(deep_sea, actions, &self.device)
+            .into_iter()
+            .position_max_by(f32::total_cmp)
+            .unwrap()
+    }
+}
+
+impl<B: Backend, M: PolicyModel<B>> DeepSeaSolver for NeuralSolver<M, B> {
+    fn choose_direction(&mut self, deep_sea: &DeepSea, _player_idx: usize) -> DiveDirection {
+        let directions = deep_sea.legal_directions();
+        let actions = directions.iter().map(|&d| d.into()).collect_vec();
+        directions[self.choose(deep_sea, &actions)]
+    }
+
+    fn take_treasure(&mut self, deep_sea: &DeepSea, _player_idx: usize) -> TreasureDecision {
+        let decisions = deep_sea.legal_treasure_decisions();
+        let actions = decisions.iter().map(|&d| d.into()).collect_vec();
+        decisions[self.choose(deep_sea, &actions)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use burn::prelude::Config;
+
+    use crate::{
+        deep_sea::DeepSea,
+        engine::Engine,
+        ml::{
+            CpuBackend,
+            dqn::{QNetwork, QNetworkConfig, state_action_size},
+            neural_solver::{
+                CheckpointMetadata, METADATA_FILE, NeuralSolver, PolicyModel, save_checkpoint,
+            },
+            ppo::{ActorCritic, ActorCriticConfig, observation_size},
+        },
+        solver::DeepSeaSolver,
+    };
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let device = Default::default();
+        let config = ActorCriticConfig::new(observation_size()).with_hidden_size(8);
+        let model: ActorCritic<CpuBackend> = config.init(&device);
+        let deep_sea = DeepSea::new(Engine::default_path(), 4);
+        let actions = deep_sea
+            .legal_directions()
+            .into_iter()
+            .map(|d| d.into())
+            .collect::<Vec<_>>();
+        let scores = model.score_actions(&deep_sea, &actions, &device);
+
+        let tempdir = tempfile::tempdir().unwrap();
+        let dir = tempdir.path();
+        save_checkpoint(dir, model, &config, 4).unwrap();
+        let solver =
+            NeuralSolver::<ActorCritic<CpuBackend>, CpuBackend>::load(dir, device).unwrap();
+        assert_eq!(
+            solver.model().score_actions(&deep_sea, &actions, &device),
+            scores
+        );
+
+        // The same model plays games of any size.
+        let solvers = (0..6)
+            .map(|_| {
+                Box::new(
+                    NeuralSolver::<ActorCritic<CpuBackend>, CpuBackend>::load(dir, device).unwrap(),
+                ) as Box<dyn DeepSeaSolver>
+            })
+            .collect();
+        assert!(Engine::make_default_game(solvers).play_one_round().is_ok());
+    }
+
+    #[test]
+    fn test_checkpoint_rejects_mismatch() {
+        let device = Default::default();
+        let config = QNetworkConfig::new(state_action_size()).with_hidden_size(8);
+        let tempdir = tempfile::tempdir().unwrap();
+        let dir = tempdir.path();
+        save_checkpoint(dir, config.init::<CpuBackend>(&device), &config, 3).unwrap();
+
+        // Wrong architecture.
+        assert!(NeuralSolver::<ActorCritic<CpuBackend>, CpuBackend>::load(dir, device).is_err());
+        assert!(NeuralSolver::<QNetwork<CpuBackend>, CpuBackend>::load(dir, device).is_ok());
+
+        // Stale feature encoding.
+        let mut metadata = CheckpointMetadata::load(dir.join(METADATA_FILE)).unwrap();
+        metadata.encoding_version += 1;
+        metadata.save(dir.join(METADATA_FILE)).unwrap();
+        assert!(NeuralSolver::<QNetwork<CpuBackend>, CpuBackend>::load(dir, device).is_err());
+    }
+}
@@ -0,0 +1,476 @@
+use burn::{
+    module::AutodiffModule,
+    nn::{Linear, LinearConfig, Relu},
+    optim::{AdamConfig, GradientsParams, Optimizer},
+    prelude::*,
+    tensor::{activation::log_softmax, backend::AutodiffBackend},
+};
+use itertools::Itertools;
+use rand::{Rng, SeedableRng, rngs::StdRng, seq::SliceRandom};
+
+use crate::{
+    deep_sea::DeepSea,
+    deep_sea_vectorization::{
+        ActionMask, DEEP_SEA_ACTION_COUNT, DeepSeaAction, DeepSeaState, EncoderConfig, Perspective,
+    },
+    error::DeepSeaResult,
+    ml::{
+        CpuBackend, batch_tensor,
+        env::VecDeepSeaEnv,
+        neural_solver::{NeuralSolver, PolicyModel},
+        vectorization::{ToBatch, Unpackable},
+        win_shares,
+    },
+};
+
+/// Logit given to illegal actions, small enough that they are never sampled.
+const ILLEGAL_LOGIT: f32 = -1e9;
+
+#[derive(Config, Debug)]
+pub struct ActorCriticConfig {
+    /// Width of an observation, see `observation_size`.
+    pub input_size: usize,
+    #[config(default = 128)]
+    pub hidden_size: usize,
+}
+
+impl ActorCriticConfig {
+    pub fn init<B: Backend>(&self, device: &B::Device) -> ActorCritic<B> {
+        ActorCritic {
+            input: LinearConfig::new(self.input_size, self.hidden_size).init(device),
+            hidden: LinearConfig::new(self.hidden_size, self.hidden_size).init(device),
+            policy: LinearConfig::new(self.hidden_size, DEEP_SEA_ACTION_COUNT).init(device),
+            value: LinearConfig::new(self.hidden_size, 1).init(device),
+            activation: Relu::new(),
+        }
+    }
+}
+
+/// Shared trunk with a policy head over every `DeepSeaAction` slot and a value head estimating
+/// the acting player's win share.
+#[derive(Module, Debug)]
+pub struct ActorCritic<B: Backend> {
+    input: Linear<B>,
+    hidden: Linear<B>,
+    policy: Linear<B>,
+    value: Linear<B>,
+    activation: Relu,
+}
+
+impl<B: Backend> ActorCritic<B> {
+    /// Maps a `[batch, input_size]` tensor to `[batch, DEEP_SEA_ACTION_COUNT]` logits and
+    /// `[batch, 1]` values.
+    pub fn forward(&self, input: Tensor<B, 2>) -> (Tensor<B, 2>, Tensor<B, 2>) {
+        let x = self.activation.forward(self.input.forward(input));
+        let x = self.activation.forward(self.hidden.forward(x));
+        (self.policy.forward(x.clone()), self.value.forward(x))
+    }
+}
+
+/// Log-probabilities over action slots, with every slot outside `mask` pushed to zero probability.
+pub fn masked_log_softmax<B: Backend>(
+    logits: Tensor<B, 2>,
+    mask: Tensor<B, 2, Bool>,
+) -> Tensor<B, 2> {
+    log_softmax(logits.mask_fill(mask.bool_not(), ILLEGAL_LOGIT), 1)
+}
+
+/// The game as seen by the acting player.
+pub(crate) fn observation(deep_sea: &DeepSea) -> Vec<f32> {
+    Perspective::acting(deep_sea)
+        .observe(deep_sea)
+        .unpack_with::<f32>(EncoderConfig::padded())
+        .collect()
+}
+
+/// Width of an observation, whatever the game.
+pub fn observation_size() -> usize {
+    DeepSeaState::default().unpacked_size_with(EncoderConfig::padded())
+}
+
+fn mask_tensor<B: Backend>(masks: &[ActionMask], device: &B::Device) -> Tensor<B, 2, Bool> {
+    let flags = masks
+        .iter()
+        .flat_map(|mask| mask.as_slice().iter().copied())
+        .collect_vec();
+    Tensor::from_data(
+        TensorData::new(flags, [masks.len(), DEEP_SEA_ACTION_COUNT]),
+        device,
+    )
+}
+
+impl<B: Backend> PolicyModel<B> for ActorCritic<B> {
+    const NAME: &'static str = "ActorCritic";
+
+    type Config = ActorCriticConfig;
+
+    fn init(config: &ActorCriticConfig, device: &B::Device) -> Self {
+        config.init(device)
+    }
+
+    fn input_size() -> usize {
+        observation_size()
+    }
+
+    /// Scores actions by their logits.
+    fn score_actions(
+        &self,
+        deep_sea: &DeepSea,
+        actions: &[DeepSeaAction],
+        device: &B::Device,
+    ) -> Vec<f32> {
+        let state = Perspective::acting(deep_sea).observe(deep_sea);
+        let input = Tensor::from_data(
+            [state].to_batch_tensordata_with::<f32>(EncoderConfig::padded()),
+            device,
+        );
+        let (logits, _) = self.forward(input);
+        let logits: Vec<f32> = logits.into_data().convert::<f32>().to_vec().unwrap();
+        actions
+            .iter()
+            .map(|action| logits[action.index()])
+            .collect()
+    }
+}
+
+/// Plays the legal action with the highest logit.
+pub type PpoSolver<B = CpuBackend> = NeuralSolver<ActorCritic<B>, B>;
+
+#[derive(Config, Debug)]
+pub struct PpoConfig {
+    #[config(default = 6)]
+    pub num_players: usize,
+    #[config(default = 128)]
+    pub hidden_size: usize,
+    #[config(default = 200)]
+    pub num_iterations: usize,
+    /// Games played in lockstep per iteration.
+    #[config(default = 32)]
+    pub num_envs: usize,
+    #[config(default = 4)]
+    pub num_epochs: usize,
+    #[config(default = 256)]
+    pub minibatch_size: usize,
+    #[config(default = 3e-4)]
+    pub learning_rate: f64,
+    #[config(default = 1.0)]
+    pub gamma: f32,
+    #[config(default = 0.95)]
+    pub gae_lambda: f32,
+    #[config(default = 0.2)]
+    pub clip_epsilon: f32,
+    #[config(default = 0.5)]
+    pub value_coef: f32,
+    #[config(default = 0.01)]
+    pub entropy_coef: f32,
+    #[config(default = 0)]
+    pub seed: u64,
+}
+
+impl PpoConfig {
+    pub fn network(&self) -> ActorCriticConfig {
+        ActorCriticConfig::new(observation_size()).with_hidden_size(self.hidden_size)
+    }
+}
+
+struct Step {
+    observation: Vec<f32>,
+    mask: ActionMask,
+    action: usize,
+    log_prob: f32,
+    value: f32,
+}
+
+struct Sample {
+    step: Step,
+    advantage: f32,
+    value_target: f32,
+}
+
+/// Samples a slot from log-probabilities, only considering slots in `mask`.
+fn sample_action(log_probs: &[f32], mask: &ActionMask, rng: &mut impl Rng) -> usize {
+    let mut choice = rng.random::<f32>();
+    let legal = mask.actions().map(|action| action.index()).collect_vec();
+    for &idx in &legal {
+        choice -= log_probs[idx].exp();
+        if choice <= 0. {
+            return idx;
+        }
+    }
+    // Rounding can leave a sliver of probability unassigned.
+    *legal.last().unwrap()
+}
+
+/// Computes generalized advantage estimates for one player's trajectory, which is only rewarded
+/// at its final step.
+fn player_samples(steps: Vec<Step>, reward: f32, config: &PpoConfig) -> Vec<Sample> {
+    let mut next_value = 0.;
+    let mut advantage = 0.;
+    let mut final_step = true;
+    let mut samples = steps
+        .into_iter()
+        .rev()
+        .map(|step| {
+            let reward = if final_step { reward } else { 0. };
+            final_step = false;
+            let delta = reward + config.gamma * next_value - step.value;
+            advantage = delta + config.gamma * config.gae_lambda * advantage;
+            next_value = step.value;
+            Sample {
+                advantage,
+                value_target: advantage + step.value,
+                step,
+            }
+        })
+        .collect_vec();
+    samples.reverse();
+    samples
+}
+
+/// Plays `num_envs` self-play games to completion, batching every seat's decisions.
+fn rollout<B: Backend>(
+    model: &ActorCritic<B>,
+    config: &PpoConfig,
+    rng: &mut impl Rng,
+    device: &B::Device,
+) -> DeepSeaResult<Vec<Sample>> {
+    let mut envs = VecDeepSeaEnv::with_rng(config.num_envs, config.num_players, rng)?;
+    let mut trajectories = (0..config.num_envs)
+        .map(|_| (0..config.num_players).map(|_| vec![]).collect_vec())
+        .collect_vec();
+
+    while !envs.done() {
+        let active = envs.active();
+        let observations = active
+            .iter()
+            .map(|&idx| observation(envs.envs()[idx].state()))
+            .collect_vec();
+        let masks = active
+            .iter()
+            .map(|&idx| envs.envs()[idx].action_mask())
+            .collect_vec();
+
+        let (logits, values) = model.forward(batch_tensor(&observations, device));
+        let log_probs: Vec<f32> = masked_log_softmax(logits, mask_tensor(&masks, device))
+            .into_data()
+            .convert::<f32>()
+            .to_vec()
+            .unwrap();
+        let values: Vec<f32> = values.into_data().convert::<f32>().to_vec().unwrap();
+
+        let mut actions = vec![];
+        for (row, (observation, mask)) in observations.into_iter().zip(masks).enumerate() {
+            let env_idx = active[row];
+            let log_probs = &log_probs[row * DEEP_SEA_ACTION_COUNT..][..DEEP_SEA_ACTION_COUNT];
+            let action = sample_action(log_probs, &mask, rng);
+            let player_idx = envs.envs()[env_idx].state().player_idx();
+            trajectories[env_idx][player_idx].push(Step {
+                observation,
+                mask,
+                action,
+                log_prob: log_probs[action],
+                value: values[row],
+            });
+            actions.push((env_idx, DeepSeaAction::from_index(action).unwrap()));
+        }
+        envs.step(&actions)?;
+    }
+
+    Ok(envs
+        .envs()
+        .iter()
+        .zip(trajectories)
+        .flat_map(|(env, players)| {
+            let rewards = win_shares(env.scores());
+            players
+                .into_iter()
+                .zip(rewards)
+                .flat_map(|(steps, reward)| player_samples(steps, reward, config))
+                .collect_vec()
+        })
+        .collect())
+}
+
+fn optimize<B: AutodiffBackend, O: Optimizer<ActorCritic<B>, B>>(
+    mut model: ActorCritic<B>,
+    optim: &mut O,
+    samples: &[Sample],
+    config: &PpoConfig,
+    rng: &mut impl Rng,
+    device: &B::Device,
+) -> ActorCritic<B> {
+    let mean = samples.iter().map(|s| s.advantage).sum::<f32>() / samples.len() as f32;
+    let std = (samples
+        .iter()
+        .map(|s| (s.advantage - mean).powi(2))
+        .sum::<f32>()
+        / samples.len() as f32)
+        .sqrt();
+
+    let mut order = (0..samples.len()).collect_vec();
+    for _ in 0..config.num_epochs {
+        order.shuffle(rng);
+        for minibatch in order.chunks(config.minibatch_size) {
+            let batch = minibatch.iter().map(|&idx| &samples[idx]).collect_vec();
+            let len = batch.len();
+            let column = |values: Vec<f32>| {
+                Tensor::<B, 2>::from_data(TensorData::new(values, [len, 1]), device)
+            };
+
+            let observations = batch
+                .iter()
+                .map(|s| s.step.observation.clone())
+                .collect_vec();
+            let masks = batch.iter().map(|s| s.step.mask).collect_vec();
+            let actions = Tensor::<B, 2, Int>::from_data(
+                TensorData::new(
+                    batch.iter().map(|s| s.step.action as i64).collect_vec(),
+                    [len, 1],
+                )
+                .convert::<B::IntElem>(),
+                device,
+            );
+            let old_log_probs = column(batch.iter().map(|s| s.step.log_prob).collect());
+            let advantages = column(
+                batch
+                    .iter()
+                    .map(|s| (s.advantage - mean) / (std + 1e-8))
+                    .collect(),
+            );
+            let value_targets = column(batch.iter().map(|s| s.value_target).collect());
+
+            let (logits, values) = model.forward(batch_tensor(&observations, device));
+            let log_probs = masked_log_softmax(logits, mask_tensor(&masks, device));
+            let ratio = (log_probs.clone().gather(1, actions) - old_log_probs).exp();
+            let clipped = ratio
+                .clone()
+                .clamp(1. - config.clip_epsilon, 1. + config.clip_epsilon);
+            let policy_loss = (ratio * advantages.clone())
+                .min_pair(clipped * advantages)
+                .mean()
+                .neg();
+            let value_loss = (values - value_targets).powf_scalar(2.).mean();
+            let entropy = (log_probs.clone().exp() * log_probs)
+                .sum_dim(1)
+                .mean()
+                .neg();
+            let loss = policy_loss + value_loss.mul_scalar(config.value_coef)
+                - entropy.mul_scalar(config.entropy_coef);
+
+            let grads = GradientsParams::from_grads(loss.backward(), &model);
+            model = optim.step(config.learning_rate, model, grads);
+        }
+    }
+    model
+}
+
+/// Trains an actor-critic through self-play, every seat being played by the current policy.
+pub fn train<B: AutodiffBackend>(
+    config: &PpoConfig,
+    device: &B::Device,
+) -> DeepSeaResult<ActorCritic<B>> {
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let mut model: ActorCritic<B> = config.network().init(device);
+    let mut optim = AdamConfig::new().init();
+
+    for _ in 0..config.num_iterations {
+        let samples = rollout(&model.valid(), config, &mut rng, device)?;
+        model = optimize(model, &mut optim, &samples, config, &mut rng, device);
+    }
+
+    Ok(model)
+}
+
+#[cfg(test)]
+mod tests {
+    use burn::{module::AutodiffModule, prelude::*};
+
+    use crate::{
+        deep_sea::{DeepSea, DiveDirection},
+        deep_sea_vectorization::{ActionMask, DEEP_SEA_ACTION_COUNT, DeepSeaAction},
+        engine::Engine,
+        ml::{
+            CpuBackend, TrainingBackend,
+            neural_solver::save_checkpoint,
+            ppo::{
+                PpoConfig, PpoSolver, Step, mask_tensor, masked_log_softmax, player_samples, train,
+            },
+        },
+        solver::{DeepSeaSolver, TreasureDecision},
+        treasure::Treasure,
+    };
+
+    #[test]
+    fn test_masked_log_softmax() {
+        let device = Default::default();
+        let mask = ActionMask::from_actions([
+            DeepSeaAction::TreasureDecision(TreasureDecision::Ignore),
+            DeepSeaAction::TreasureDecision(TreasureDecision::Return(Treasure::Three)),
+        ]);
+        let logits = Tensor::<CpuBackend, 2>::from_data(
+            TensorData::new(
+                vec![0f32; DEEP_SEA_ACTION_COUNT],
+                [1, DEEP_SEA_ACTION_COUNT],
+            ),
+            &device,
+        );
+        let probs: Vec<f32> = masked_log_softmax(logits, mask_tensor(&[mask], &device))
+            .exp()
+            .into_data()
+            .to_vec()
+            .unwrap();
+        for (idx, prob) in probs.into_iter().enumerate() {
+            match idx {
+                0 | 4 => assert!((prob - 0.5).abs() < 1e-6),
+                _ => assert_eq!(prob, 0.),
+            }
+        }
+    }
+
+    #[test]
+    fn test_player_samples() {
+        let config = PpoConfig::new().with_gae_lambda(1.);
+        let step = |value| Step {
+            observation: vec![],
+            mask: ActionMask::default(),
+            action: 0,
+            log_prob: 0.,
+            value,
+        };
+        let samples = player_samples(vec![step(0.25), step(0.5)], 1., &config);
+        // Without discounting, every step's target is the final reward.
+        assert_eq!(samples[0].value_target, 1.);
+        assert_eq!(samples[1].value_target, 1.);
+        assert_eq!(samples[0].advantage, 0.75);
+        assert_eq!(samples[1].advantage, 0.5);
+    }
+
+    #[test]
+    fn test_train_save_and_load() {
+        let config = PpoConfig::new()
+            .with_num_players(3)
+            .with_hidden_size(8)
+            .with_num_iterations(2)
+            .with_num_envs(2)
+            .with_num_epochs(1)
+            .with_minibatch_size(32);
+        let device = Default::default();
+        let model = train::<TrainingBackend>(&config, &device).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        save_checkpoint(dir.path(), model.valid(), &config.network(), 3).unwrap();
+        let mut solver = PpoSolver::<CpuBackend>::load(dir.path(), device).unwrap();
+
+        let solvers = (0..3)
+            .map(|_| {
+                Box::new(PpoSolver::<CpuBackend>::load(dir.path(), device).unwrap())
+                    as Box<dyn DeepSeaSolver>
+            })
+            .collect();
+        let scores = Engine::make_default_game(solvers).play_one_round().unwrap();
+        assert_eq!(scores.len(), 3);
+
+        let deep_sea = DeepSea::new(Engine::default_path(), 3);
+        assert_eq!(solver.choose_direction(&deep_sea, 0), DiveDirection::Down);
+    }
+}
@@ -0,0 +1,393 @@
+use burn::prelude::*;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+use crate::{
+    deep_sea_vectorization::{DeepSeaStateActionPair, EncoderConfig},
+    ml::vectorization::Unpackable,
+};
+
+/// Added to every priority so that no transition becomes impossible to sample.
+const PRIORITY_EPSILON: f32 = 1e-3;
+
+/// A step between two decisions of the same player, vectorized for a state-action network.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transition {
+    pub state_action: Vec<f32>,
+    pub reward: f32,
+    /// Alternatives at the same player's next decision, empty if the game ended.
+    pub next_candidates: Vec<Vec<f32>>,
+}
+
+impl Transition {
+    /// Vectorizes `state_action` and `next_candidates` with the encoding models are fed.
+    pub fn new(
+        state_action: &DeepSeaStateActionPair,
+        reward: f32,
+        next_candidates: &[DeepSeaStateActionPair],
+    ) -> Self {
+        let unpack = |pair: &DeepSeaStateActionPair| {
+            pair.unpack_with::<f32>(EncoderConfig::padded()).collect()
+        };
+        Self {
+            state_action: unpack(state_action),
+            reward,
+            next_candidates: next_candidates.iter().map(unpack).collect(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sampling {
+    Uniform,
+    /// Samples transitions in proportion to their priority raised to `alpha`, correcting the
+    /// resulting bias with importance weights annealed by `beta`.
+    Prioritized {
+        alpha: f32,
+        beta: f32,
+    },
+}
+
+/// Binary tree of priorities in which every node holds the sum of its children, so that
+/// sampling in proportion to priority and updating a priority are both logarithmic.
+#[derive(Clone, Debug)]
+struct SumTree {
+    /// Number of leaves, a power of two.
+    leaves: usize,
+    /// The root is at 1 and the children of `n` at `2n` and `2n + 1`.
+    nodes: Vec<f32>,
+}
+
+impl SumTree {
+    fn new(capacity: usize) -> Self {
+        let leaves = capacity.next_power_of_two();
+        Self {
+            leaves,
+            nodes: vec![0.; 2 * leaves],
+        }
+    }
+
+    fn total(&self) -> f32 {
+        self.nodes[1]
+    }
+
+    fn get(&self, idx: usize) -> f32 {
+        self.nodes[self.leaves + idx]
+    }
+
+    fn set(&mut self, idx: usize, priority: f32) {
+        let mut node = self.leaves + idx;
+        self.nodes[node] = priority;
+        while node > 1 {
+            node /= 2;
+            self.nodes[node] = self.nodes[2 * node] + self.nodes[2 * node + 1];
+        }
+    }
+
+    /// The leaf at which the running sum of priorities exceeds `value`.
+    fn find(&self, mut value: f32) -> usize {
+        let mut node = 1;
+        while node < self.leaves {
+            let left = 2 * node;
+            if value < self.nodes[left] || self.nodes[left + 1] == 0. {
+                node = left;
+            } else {
+                value -= self.nodes[left];
+                node = left + 1;
+            }
+        }
+        node - self.leaves
+    }
+}
+
+/// Transitions drawn from a `ReplayBuffer`.
+pub struct ReplayBatch<'a> {
+    /// Positions in the buffer, to pass back to `ReplayBuffer::update_priorities`.
+    pub indices: Vec<usize>,
+    pub transitions: Vec<&'a Transition>,
+    /// Importance weights, all 1 unless sampling is prioritized.
+    pub weights: Vec<f32>,
+}
+
+impl ReplayBatch<'_> {
+    pub fn len(&self) -> usize {
+        self.transitions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transitions.is_empty()
+    }
+
+    /// `[batch, state_action width]`.
+    pub fn state_actions<B: Backend>(&self, device: &B::Device) -> Tensor<B, 2> {
+        let width = self.transitions.first().map_or(0, |t| t.state_action.len());
+        let mut buffer = Vec::with_capacity(self.len() * width);
+        for transition in &self.transitions {
+            buffer.extend_from_slice(&transition.state_action);
+        }
+        Tensor::from_data(TensorData::new(buffer, [self.len(), width]), device)
+    }
+
+    /// `[batch, 1]`.
+    pub fn rewards<B: Backend>(&self, device: &B::Device) -> Tensor<B, 2> {
+        let rewards = self.transitions.iter().map(|t| t.reward).collect();
+        Tensor::from_data(TensorData::new(rewards, [self.len(), 1]), device)
+    }
+
+    /// `[batch, 1]`.
+    pub fn weights<B: Backend>(&self, device: &B::Device) -> Tensor<B, 2> {
+        Tensor::from_data(
+            TensorData::new(self.weights.clone(), [self.len(), 1]),
+            device,
+        )
+    }
+}
+
+/// Fixed-capacity ring of transitions which overwrites the oldest once full. Sampling is
+/// reproducible given the seed.
+pub struct ReplayBuffer {
+    capacity: usize,
+    transitions: Vec<Transition>,
+    /// Where the next transition is written.
+    next: usize,
+    sampling: Sampling,
+    priorities: SumTree,
+    /// New transitions get the highest priority seen so far, so each is sampled at least once
+    /// with good odds.
+    max_priority: f32,
+    rng: StdRng,
+}
+
+impl ReplayBuffer {
+    pub fn new(capacity: usize, sampling: Sampling, seed: u64) -> Self {
+        assert!(capacity > 0, "Replay buffer capacity must be positive");
+        Self {
+            capacity,
+            transitions: Vec::with_capacity(capacity),
+            next: 0,
+            sampling,
+            priorities: SumTree::new(capacity),
+            max_priority: 1.,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.transitions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transitions.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn push(&mut self, transition: Transition) {
+        if self.transitions.len() < self.capacity {
+            self.transitions.push(transition);
+        } else {
+            self.transitions[self.next] = transition;
+        }
+        self.priorities.set(self.next, self.max_priority);
+        self.next = (self.next + 1) % self.capacity;
+    }
+
+    /// Draws `batch_size` transitions with replacement, or none if the buffer is empty.
+    pub fn sample(&mut self, batch_size: usize) -> ReplayBatch<'_> {
+        if self.is_empty() {
+            return ReplayBatch {
+                indices: vec![],
+                transitions: vec![],
+                weights: vec![],
+            };
+        }
+        let (indices, weights) = match self.sampling {
+            Sampling::Uniform => (
+                (0..batch_size)
+                    .map(|_| self.rng.random_range(0..self.len()))
+                    .collect(),
+                vec![1.; batch_size],
+            ),
+            Sampling::Prioritized { beta, .. } => self.sample_prioritized(batch_size, beta),
+        };
+        ReplayBatch {
+            transitions: indices.iter().map(|&idx| &self.transitions[idx]).collect(),
+            indices,
+            weights,
+        }
+    }
+
+    /// Samples one transition from each of `batch_size` equal slices of the total priority.
+    fn sample_prioritized(&mut self, batch_size: usize, beta: f32) -> (Vec<usize>, Vec<f32>) {
+        let total = self.priorities.total();
+        let segment = total / batch_size as f32;
+        let indices: Vec<usize> = (0..batch_size)
+            .map(|i| {
+                let value = segment * (i as f32 + self.rng.random::<f32>());
+                // Rounding may land past the last transition.
+                self.priorities.find(value).min(self.len() - 1)
+            })
+            .collect();
+        let weights: Vec<f32> = indices
+            .iter()
+            .map(|&idx| {
+                let probability = self.priorities.get(idx) / total;
+                (self.len() as f32 * probability).powf(-beta)
+            })
+            .collect();
+        let max_weight = weights.iter().cloned().fold(f32::MIN_POSITIVE, f32::max);
+        (
+            indices,
+            weights.into_iter().map(|w| w / max_weight).collect(),
+        )
+    }
+
+    /// Sets the priorities of sampled transitions from their new temporal-difference errors.
+    /// Does nothing unless sampling is prioritized.
+    pub fn update_priorities(&mut self, indices: &[usize], td_errors: &[f32]) {
+        let Sampling::Prioritized { alpha, .. } = self.sampling else {
+            return;
+        };
+        for (&idx, &td_error) in indices.iter().zip(td_errors) {
+            let priority = (td_error.abs() + PRIORITY_EPSILON).powf(alpha);
+            self.max_priority = self.max_priority.max(priority);
+            self.priorities.set(idx, priority);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        deep_sea::DiveDirection,
+        deep_sea_vectorization::{DeepSeaAction, DeepSeaState, DeepSeaStateActionPair},
+        ml::{
+            CpuBackend,
+            dqn::state_action_size,
+            replay::{ReplayBuffer, Sampling, SumTree, Transition},
+        },
+    };
+
+    fn transition(value: f32) -> Transition {
+        Transition {
+            state_action: vec![value; 3],
+            reward: value,
+            next_candidates: vec![],
+        }
+    }
+
+    #[test]
+    fn test_sum_tree() {
+        let mut tree = SumTree::new(5);
+        for (idx, priority) in [1., 2., 3., 4., 0.].into_iter().enumerate() {
+            tree.set(idx, priority);
+        }
+        assert_eq!(tree.total(), 10.);
+        assert_eq!(tree.find(0.5), 0);
+        assert_eq!(tree.find(1.), 1);
+        assert_eq!(tree.find(5.9), 2);
+        assert_eq!(tree.find(9.99), 3);
+        tree.set(3, 0.);
+        assert_eq!(tree.total(), 6.);
+        assert_eq!(tree.find(7.), 2);
+    }
+
+    #[test]
+    fn test_ring_overwrites_oldest() {
+        let mut buffer = ReplayBuffer::new(3, Sampling::Uniform, 0);
+        for value in 0..5 {
+            buffer.push(transition(value as f32));
+        }
+        assert_eq!(buffer.len(), 3);
+        let mut rewards = buffer
+            .sample(64)
+            .transitions
+            .iter()
+            .map(|t| t.reward as u32)
+            .collect::<Vec<_>>();
+        rewards.sort();
+        rewards.dedup();
+        assert_eq!(rewards, vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn test_sampling_is_seeded() {
+        let sampled = |sampling, seed| {
+            let mut buffer = ReplayBuffer::new(16, sampling, seed);
+            for value in 0..16 {
+                buffer.push(transition(value as f32));
+            }
+            buffer.update_priorities(&[3, 7], &[5., 10.]);
+            buffer.sample(8).indices
+        };
+        let prioritized = Sampling::Prioritized {
+            alpha: 0.6,
+            beta: 0.4,
+        };
+        for sampling in [Sampling::Uniform, prioritized] {
+            assert_eq!(sampled(sampling, 1), sampled(sampling, 1));
+            assert_ne!(sampled(sampling, 1), sampled(sampling, 2));
+        }
+    }
+
+    #[test]
+    fn test_prioritized_sampling() {
+        let mut buffer = ReplayBuffer::new(
+            4,
+            Sampling::Prioritized {
+                alpha: 1.,
+                beta: 1.,
+            },
+            0,
+        );
+        for value in 0..4 {
+            buffer.push(transition(value as f32));
+        }
+        // Priorities of roughly 1, 1, 1 and 7.
+        buffer.update_priorities(&[0, 1, 2, 3], &[1., 1., 1., 7.]);
+
+        let batch = buffer.sample(100);
+        let hot = batch.indices.iter().filter(|&&idx| idx == 3).count();
+        assert!((65..=75).contains(&hot), "{hot}");
+        // Rarely sampled transitions carry the most weight.
+        for (&idx, &weight) in batch.indices.iter().zip(&batch.weights) {
+            let expected = if idx == 3 { 1. / 7. } else { 1. };
+            assert!((weight - expected).abs() < 1e-3, "{idx}: {weight}");
+        }
+    }
+
+    #[test]
+    fn test_batch_tensors() {
+        let state = DeepSeaState::default();
+        let pair = |action| DeepSeaStateActionPair {
+            state: &state,
+            action,
+        };
+        let up = DeepSeaAction::DiveDirection(DiveDirection::Up);
+        let down = DeepSeaAction::DiveDirection(DiveDirection::Down);
+        let mut buffer = ReplayBuffer::new(8, Sampling::Uniform, 0);
+        buffer.push(Transition::new(
+            &pair(&down),
+            0.5,
+            &[pair(&down), pair(&up)],
+        ));
+        assert_eq!(buffer.sample(1).transitions[0].next_candidates.len(), 2);
+
+        let device = Default::default();
+        let batch = buffer.sample(5);
+        assert_eq!(
+            batch.state_actions::<CpuBackend>(&device).dims(),
+            [5, state_action_size()]
+        );
+        assert_eq!(batch.rewards::<CpuBackend>(&device).dims(), [5, 1]);
+        assert_eq!(
+            batch
+                .weights::<CpuBackend>(&device)
+                .into_data()
+                .to_vec::<f32>()
+                .unwrap(),
+            vec![1.; 5]
+        );
+    }
+}
@@ -1,5 +1,12 @@
+use std::fmt;
+
 use burn::{prelude::TensorData, tensor::Element};
 
+use crate::{
+    deep_sea_vectorization::EncoderConfig,
+    error::{DeepSeaError, DeepSeaResult},
+};
+
 pub trait DataType: Clone + num::Zero + num::One + From<u16> + From<bool> + Element {}
 impl<T> DataType for T where T: Clone + num::Zero + num::One + From<u16> + From<bool> + Element {}
 
@@ -24,6 +31,41 @@ where
     }
 }
 
+/// How a value is turned into features.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    /// The value itself as a single integer feature.
+    #[default]
+    Raw,
+    /// One feature per possible value, all zero except for the value's.
+    OneHot,
+    /// A single feature scaled into [0, 1].
+    Normalized,
+}
+
+impl Encoding {
+    /// Number of features a value with `num_values` possibilities is encoded into.
+    pub fn size(&self, num_values: usize) -> usize {
+        match self {
+            Encoding::OneHot => num_values,
+            Encoding::Raw | Encoding::Normalized => 1,
+        }
+    }
+}
+
+/// `size` features which are all zero except for a one at `hot_idx`.
+pub fn one_hot<T: DataType>(hot_idx: usize, size: usize) -> impl Iterator<Item = T> {
+    debug_assert!(hot_idx < size);
+    std::iter::repeat_n(T::zero(), hot_idx)
+        .chain(std::iter::once(T::one()))
+        .chain(std::iter::repeat_n(T::zero(), size - hot_idx - 1))
+}
+
+/// `value` scaled from [0, `max`] into [0, 1].
+pub fn normalized<T: DataType>(value: usize, max: usize) -> T {
+    T::from_elem(value as f32 / max.max(1) as f32)
+}
+
 pub trait Into1DArray {
     fn into_ndarray<T: DataType>(self) -> ndarray::Array1<T>;
 }
@@ -33,9 +75,258 @@ pub trait IntoTensorData {
 }
 
 pub trait Unpackable {
-    fn unpack<T: DataType>(&self) -> impl Iterator<Item = T>;
+    /// Features of `self` under `config`. Always yields `unpacked_size_with(config)` values.
+    fn unpack_with<T: DataType>(&self, config: EncoderConfig) -> impl Iterator<Item = T>;
+
+    fn unpacked_size_with(&self, config: EncoderConfig) -> usize;
+
+    /// Writes the features of `self` under `config` straight into `out`, which must be exactly
+    /// `unpacked_size_with(config)` long, without allocating.
+    fn unpack_into<T: DataType>(&self, config: EncoderConfig, out: &mut [T]) {
+        assert_eq!(
+            out.len(),
+            self.unpacked_size_with(config),
+            "Buffer does not fit the unpacked features"
+        );
+        for (slot, value) in out.iter_mut().zip(self.unpack_with(config)) {
+            *slot = value;
+        }
+    }
+
+    fn unpack<T: DataType>(&self) -> impl Iterator<Item = T> {
+        self.unpack_with(EncoderConfig::default())
+    }
+
+    fn unpacked_size(&self) -> usize {
+        self.unpacked_size_with(EncoderConfig::default())
+    }
+}
+
+/// The kind of value held by a feature.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FeatureKind {
+    /// A raw integer.
+    Integer,
+    /// 0 or 1, such as a single slot of a one-hot encoding.
+    Flag,
+    /// A value scaled into [0, 1].
+    Fraction,
+}
+
+impl From<Encoding> for FeatureKind {
+    fn from(encoding: Encoding) -> Self {
+        match encoding {
+            Encoding::Raw => FeatureKind::Integer,
+            Encoding::OneHot => FeatureKind::Flag,
+            Encoding::Normalized => FeatureKind::Fraction,
+        }
+    }
+}
+
+/// A named column of an unpacked vector, e.g. `players[2].held_treasures[3]`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Feature {
+    pub name: String,
+    pub kind: FeatureKind,
+}
+
+impl Feature {
+    pub fn new(name: impl Into<String>, kind: FeatureKind) -> Self {
+        Self {
+            name: name.into(),
+            kind,
+        }
+    }
+}
+
+/// Joins a field name onto the name of its parent.
+pub fn field_name(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{prefix}.{name}")
+    }
+}
+
+/// The columns of an unpacked vector, in order. Displays as one tab-separated
+/// `index, name, kind` line per column.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct FeatureSchema {
+    features: Vec<Feature>,
+}
 
-    fn unpacked_size(&self) -> usize;
+impl FeatureSchema {
+    pub fn features(&self) -> &[Feature] {
+        &self.features
+    }
+
+    pub fn len(&self) -> usize {
+        self.features.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.features.is_empty()
+    }
+
+    /// Index of the column called `name`.
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.features.iter().position(|f| f.name == name)
+    }
+
+    /// Pairs each column with its value in `values`, a vector unpacked with this schema.
+    pub fn decode<'a, T: Copy>(
+        &'a self,
+        values: &'a [T],
+    ) -> impl Iterator<Item = (&'a Feature, T)> + 'a {
+        debug_assert_eq!(values.len(), self.len());
+        self.features.iter().zip(values.iter().copied())
+    }
+
+    /// A `name = value` line for each non-zero column of `values`.
+    pub fn describe<T: DataType + fmt::Display>(&self, values: &[T]) -> String {
+        self.decode(values)
+            .filter(|(_, value)| !value.is_zero())
+            .map(|(feature, value)| format!("{} = {value}\n", feature.name))
+            .collect()
+    }
+}
+
+impl fmt::Display for FeatureSchema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, feature) in self.features.iter().enumerate() {
+            writeln!(f, "{idx}\t{}\t{:?}", feature.name, feature.kind)?;
+        }
+        Ok(())
+    }
+}
+
+/// Names the features yielded by `Unpackable::unpack_with`.
+pub trait Describable: Unpackable {
+    /// Appends one `Feature` per value of `unpack_with(config)`, named under `prefix`.
+    fn describe_into(&self, prefix: &str, config: EncoderConfig, features: &mut Vec<Feature>);
+
+    fn schema(&self, config: EncoderConfig) -> FeatureSchema {
+        let mut features = Vec::with_capacity(self.unpacked_size_with(config));
+        self.describe_into("", config, &mut features);
+        FeatureSchema { features }
+    }
+}
+
+/// Inverse of `Unpackable`, rebuilding values from their features.
+pub trait Packable: Unpackable + Sized {
+    /// What has to be known besides the features to rebuild a value, such as a number of
+    /// players.
+    type Shape: Copy;
+
+    /// Reads a value from the front of `values`, which were unpacked with `config`, and advances
+    /// `values` past it.
+    fn read_from<T: DataType>(
+        values: &mut &[T],
+        shape: Self::Shape,
+        config: EncoderConfig,
+    ) -> DeepSeaResult<Self>;
+
+    /// Rebuilds a value from exactly `values`.
+    fn pack_with<T: DataType>(
+        values: &[T],
+        shape: Self::Shape,
+        config: EncoderConfig,
+    ) -> DeepSeaResult<Self> {
+        let mut rest = values;
+        let packed = Self::read_from(&mut rest, shape, config)?;
+        if rest.is_empty() {
+            Ok(packed)
+        } else {
+            Err(DeepSeaError::CorruptReplay(format!(
+                "{} of {} values left over after packing",
+                rest.len(),
+                values.len()
+            )))
+        }
+    }
+
+    fn pack<T: DataType>(values: &[T], shape: Self::Shape) -> DeepSeaResult<Self> {
+        Self::pack_with(values, shape, EncoderConfig::default())
+    }
+
+    fn from_ndarray<T: DataType>(
+        array: &ndarray::Array1<T>,
+        shape: Self::Shape,
+    ) -> DeepSeaResult<Self> {
+        Self::pack(&array.to_vec(), shape)
+    }
+
+    fn from_tensordata<T: DataType>(data: &TensorData, shape: Self::Shape) -> DeepSeaResult<Self> {
+        let values = data
+            .to_vec::<T>()
+            .map_err(|e| DeepSeaError::CorruptReplay(format!("Cannot read tensor data: {e:?}")))?;
+        Self::pack(&values, shape)
+    }
+}
+
+/// Splits the first `n` values off `values`.
+pub fn take_values<'a, T>(values: &mut &'a [T], n: usize) -> DeepSeaResult<&'a [T]> {
+    if values.len() < n {
+        return Err(DeepSeaError::CorruptReplay(format!(
+            "Expected {n} more values, found {}",
+            values.len()
+        )));
+    }
+    let (taken, rest) = values.split_at(n);
+    *values = rest;
+    Ok(taken)
+}
+
+/// Unpacks equally sized items as the rows of one matrix, writing every row straight into a
+/// single preallocated buffer.
+pub trait ToBatch {
+    /// Appends the rows to `buffer` and returns their width.
+    fn unpack_rows_into<T: DataType>(&self, config: EncoderConfig, buffer: &mut Vec<T>) -> usize;
+
+    fn to_array2_with<T: DataType>(&self, config: EncoderConfig) -> ndarray::Array2<T>;
+
+    /// A `[rows, width]` tensor.
+    fn to_batch_tensordata_with<T: DataType>(&self, config: EncoderConfig) -> TensorData;
+
+    fn to_array2<T: DataType>(&self) -> ndarray::Array2<T> {
+        self.to_array2_with(EncoderConfig::default())
+    }
+
+    fn to_batch_tensordata<T: DataType>(&self) -> TensorData {
+        self.to_batch_tensordata_with::<T>(EncoderConfig::default())
+    }
+}
+
+impl<U: Unpackable> ToBatch for [U] {
+    fn unpack_rows_into<T: DataType>(&self, config: EncoderConfig, buffer: &mut Vec<T>) -> usize {
+        let width = self.first().map_or(0, |x| x.unpacked_size_with(config));
+        if width == 0 {
+            return 0;
+        }
+        let start = buffer.len();
+        buffer.resize(start + self.len() * width, T::zero());
+        for (x, row) in self.iter().zip(buffer[start..].chunks_exact_mut(width)) {
+            assert_eq!(
+                x.unpacked_size_with(config),
+                width,
+                "Batched rows must have the same width"
+            );
+            x.unpack_into(config, row);
+        }
+        width
+    }
+
+    fn to_array2_with<T: DataType>(&self, config: EncoderConfig) -> ndarray::Array2<T> {
+        let mut buffer = vec![];
+        let width = self.unpack_rows_into(config, &mut buffer);
+        ndarray::Array2::from_shape_vec((self.len(), width), buffer).unwrap()
+    }
+
+    fn to_batch_tensordata_with<T: DataType>(&self, config: EncoderConfig) -> TensorData {
+        let mut buffer: Vec<T> = vec![];
+        let width = self.unpack_rows_into(config, &mut buffer);
+        TensorData::new(buffer, vec![self.len(), width])
+    }
 }
 
 impl<U: Unpackable> Into1DArray for U {
@@ -54,21 +345,58 @@ impl<U: Unpackable> IntoTensorData for U {
 }
 
 impl<U: Unpackable> Unpackable for Vec<U> {
-    fn unpack<T: DataType>(&self) -> impl Iterator<Item = T> {
-        self.iter().flat_map(|x| x.unpack::<T>())
+    fn unpack_with<T: DataType>(&self, config: EncoderConfig) -> impl Iterator<Item = T> {
+        self.iter().flat_map(move |x| x.unpack_with::<T>(config))
     }
 
-    fn unpacked_size(&self) -> usize {
-        self.iter().fold(0, |a, b| a + b.unpacked_size())
+    fn unpack_into<T: DataType>(&self, config: EncoderConfig, out: &mut [T]) {
+        unpack_items_into(self, config, out)
+    }
+
+    fn unpacked_size_with(&self, config: EncoderConfig) -> usize {
+        self.iter().fold(0, |a, b| a + b.unpacked_size_with(config))
     }
 }
 
 impl<U: Unpackable> Unpackable for &[U] {
-    fn unpack<T: DataType>(&self) -> impl Iterator<Item = T> {
-        self.iter().flat_map(|x| x.unpack::<T>())
+    fn unpack_with<T: DataType>(&self, config: EncoderConfig) -> impl Iterator<Item = T> {
+        self.iter().flat_map(move |x| x.unpack_with::<T>(config))
     }
 
-    fn unpacked_size(&self) -> usize {
-        self.iter().fold(0, |a, b| a + b.unpacked_size())
+    fn unpack_into<T: DataType>(&self, config: EncoderConfig, out: &mut [T]) {
+        unpack_items_into(self, config, out)
+    }
+
+    fn unpacked_size_with(&self, config: EncoderConfig) -> usize {
+        self.iter().fold(0, |a, b| a + b.unpacked_size_with(config))
+    }
+}
+
+/// Unpacks each of `items` into its own stretch of `out`.
+fn unpack_items_into<U: Unpackable, T: DataType>(
+    items: &[U],
+    config: EncoderConfig,
+    out: &mut [T],
+) {
+    let mut rest = out;
+    for item in items {
+        let (head, tail) = rest.split_at_mut(item.unpacked_size_with(config));
+        item.unpack_into(config, head);
+        rest = tail;
+    }
+    assert!(rest.is_empty(), "Buffer does not fit the unpacked features");
+}
+
+impl<U: Describable> Describable for Vec<U> {
+    fn describe_into(&self, prefix: &str, config: EncoderConfig, features: &mut Vec<Feature>) {
+        self.as_slice().describe_into(prefix, config, features)
+    }
+}
+
+impl<U: Describable> Describable for &[U] {
+    fn describe_into(&self, prefix: &str, config: EncoderConfig, features: &mut Vec<Feature>) {
+        for (idx, x) in self.iter().enumerate() {
+            x.describe_into(&format!("{prefix}[{idx}]"), config, features);
+        }
     }
 }
@@ -0,0 +1,182 @@
+use std::{
+    io::BufReader,
+    process::{Child, ChildStdin, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    deep_sea::{DeepSea, DiveDirection},
+    error::{DeepSeaError, DeepSeaResult},
+    solver::{DeepSeaSolver, TreasureDecision},
+    text_solver::{DEFAULT_TIMEOUT, TextSolver},
+};
+
+/// A solver running as a local executable, spoken to over its stdin and stdout with the text
+/// protocol in `protocol`. Its stderr is passed through.
+pub struct ProcessSolver {
+    solver: TextSolver<ChildStdin>,
+    child: Child,
+}
+
+impl ProcessSolver {
+    /// Starts `command` and waits for it to answer the handshake.
+    pub fn spawn(command: &mut Command) -> DeepSeaResult<Self> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        match TextSolver::connect(stdin, stdout, DEFAULT_TIMEOUT) {
+            Ok(solver) => Ok(Self { solver, child }),
+            Err(error) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                Err(error)
+            }
+        }
+    }
+
+    /// How long to wait for each decision when the engine does not enforce a time limit.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.solver.set_timeout(timeout);
+        self
+    }
+
+    /// The name the bot gave in the handshake, if any.
+    pub fn name(&self) -> &str {
+        self.solver.name()
+    }
+}
+
+impl DeepSeaSolver for ProcessSolver {
+    fn choose_direction(&mut self, deep_sea: &DeepSea, player_idx: usize) -> DiveDirection {
+        self.solver.choose_direction(deep_sea, player_idx)
+    }
+
+    fn take_treasure(&mut self, deep_sea: &DeepSea, player_idx: usize) -> TreasureDecision {
+        self.solver.take_treasure(deep_sea, player_idx)
+    }
+
+    fn set_time_budget(&mut self, budget: Duration) {
+        self.solver.set_time_budget(budget);
+    }
+
+    fn take_error(&mut self) -> Option<DeepSeaError> {
+        self.solver.take_error()
+    }
+}
+
+impl Drop for ProcessSolver {
+    /// Asks the bot to quit, closes its stdin and kills it if it has not exited shortly after.
+    fn drop(&mut self) {
+        self.solver.close();
+        let deadline = Instant::now() + Duration::from_millis(100);
+        while matches!(self.child.try_wait(), Ok(None)) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{process::Command, time::Duration};
+
+    use crate::{
+        deep_sea::{DeepSea, DiveDirection},
+        engine::Engine,
+        error::DeepSeaError,
+        process_solver::ProcessSolver,
+        solver::{DeepSeaSolver, TreasureDecision},
+    };
+
+    /// The reference bot: names itself and answers every request with its first choice.
+    const FIRST_CHOICE_BOT: &str = r#"
+        while read -r kind rest; do
+            case "$kind" in
+                deep-sea) echo "ready first-choice" ;;
+                choose) set -- $rest; echo "$2" ;;
+                quit) exit 0 ;;
+            esac
+        done
+    "#;
+
+    fn spawn_bot(script: &str) -> ProcessSolver {
+        ProcessSolver::spawn(Command::new("sh").args(["-c", script])).unwrap()
+    }
+
+    #[test]
+    fn test_plays_a_round() {
+        let players: Vec<Box<dyn DeepSeaSolver>> = (0..3)
+            .map(|_| Box::new(spawn_bot(FIRST_CHOICE_BOT)) as Box<dyn DeepSeaSolver>)
+            .collect();
+        let result = Engine::make_default_game(players).play_round().unwrap();
+        assert_eq!(result.faults, [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_handshake() {
+        assert_eq!(spawn_bot(FIRST_CHOICE_BOT).name(), "first-choice");
+        let result = ProcessSolver::spawn(Command::new("sh").args(["-c", "echo hello"]));
+        assert!(matches!(
+            result,
+            Err(DeepSeaError::Protocol {
+                player_idx: None,
+                ..
+            })
+        ));
+        let result = ProcessSolver::spawn(&mut Command::new("/nonexistent/bot"));
+        assert!(matches!(result, Err(DeepSeaError::Io(_))));
+    }
+
+    #[test]
+    fn test_unrecognized_reply() {
+        let mut solver = spawn_bot(
+            r#"
+            read -r hello; echo ready
+            while read -r kind rest; do
+                [ "$kind" = choose ] && echo sideways
+            done
+            "#,
+        );
+        let deep_sea = DeepSea::new(Engine::default_path(), 2);
+        assert_eq!(solver.choose_direction(&deep_sea, 0), DiveDirection::Down);
+        assert!(matches!(
+            solver.take_error(),
+            Some(DeepSeaError::Protocol {
+                player_idx: Some(0),
+                ..
+            })
+        ));
+        assert!(solver.take_error().is_none());
+    }
+
+    #[test]
+    fn test_late_reply_is_skipped() {
+        let mut solver = spawn_bot(
+            r#"
+            read -r hello; echo ready
+            answer=up
+            while read -r kind rest; do
+                if [ "$kind" = choose ]; then
+                    [ "$answer" = up ] && sleep 0.3
+                    echo "$answer"; answer=take
+                fi
+            done
+            "#,
+        );
+        let deep_sea = DeepSea::new(Engine::default_path(), 2);
+        solver.set_time_budget(Duration::from_millis(50));
+        assert_eq!(solver.choose_direction(&deep_sea, 0), DiveDirection::Down);
+        assert!(matches!(
+            solver.take_error(),
+            Some(DeepSeaError::Timeout { player_idx: 0, .. })
+        ));
+
+        assert_eq!(solver.take_treasure(&deep_sea, 0), TreasureDecision::Take);
+        assert!(solver.take_error().is_none());
+    }
+}
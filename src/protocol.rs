@@ -0,0 +1,221 @@
+use std::fmt::Write;
+
+use itertools::Itertools;
+
+use crate::{
+    deep_sea::{DeepSea, DiveDirection, Position, Tile},
+    solver::TreasureDecision,
+    treasure::Treasure,
+};
+
+pub const PROTOCOL_VERSION: u32 = 1;
+
+pub const QUIT: &str = "quit\n";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecisionKind {
+    Direction,
+    Treasure,
+}
+
+/// Opens a session with a solver outside this process. The solver answers with a `ready` line,
+/// optionally followed by its name, and then receives `request`s until it is sent `QUIT`. Every
+/// message is one or more newline-terminated lines of space-separated tokens.
+pub fn hello() -> String {
+    format!("deep-sea {PROTOCOL_VERSION}\n")
+}
+
+/// The name a solver gave in its `ready` line, or `None` if the line is not a valid answer.
+pub fn parse_ready(line: &str) -> Option<&str> {
+    let rest = line.trim().strip_prefix("ready")?;
+    (rest.is_empty() || rest.starts_with(' ')).then(|| rest.trim())
+}
+
+/// The lines describing `deep_sea`, without a request. Also sent on their own as updates, which
+/// need no reply.
+pub fn state(deep_sea: &DeepSea) -> String {
+    let mut message = format!(
+        "state {} {} {}\npath {}\n",
+        deep_sea.players().len(),
+        deep_sea.player_idx(),
+        deep_sea.oxygen(),
+        deep_sea
+            .path()
+            .iter()
+            .map(|&tile| tile_token(tile))
+            .join(" ")
+    );
+    for player in deep_sea.players() {
+        let position = match player.position() {
+            Position::Diving(tile_idx) => tile_idx.to_string(),
+            Position::WaitingToDive => "waiting".to_string(),
+            Position::ReturnedToSubmarine => "returned".to_string(),
+        };
+        write!(
+            message,
+            "player {position} {}",
+            direction_token(player.direction())
+        )
+        .unwrap();
+        for &treasure in player.held_treasures() {
+            write!(message, " {}", treasure_token(treasure)).unwrap();
+        }
+        message.push('\n');
+    }
+    message
+}
+
+/// Asks the current player of `deep_sea` for a decision of `kind`: the state of the game
+/// followed by the legal choices,
+///
+/// ```text
+/// state <num_players> <player_idx> <oxygen>
+/// path <tile>...
+/// player <position> <direction> <treasure>...
+/// choose direction|treasure <choice>...
+/// ```
+///
+/// with one `player` line per seat. Tiles are `-` when empty and `1` to `4` for treasures,
+/// positions are `waiting`, `returned` or the index of a tile, and directions are `down` or
+/// `up`. The solver replies with one of the listed choices on a line of its own: a direction,
+/// `ignore`, `take` or `return:<treasure>`.
+pub fn request(deep_sea: &DeepSea, kind: DecisionKind) -> String {
+    let choices = match kind {
+        DecisionKind::Direction => deep_sea
+            .legal_directions()
+            .into_iter()
+            .map(direction_token)
+            .join(" "),
+        DecisionKind::Treasure => deep_sea
+            .legal_treasure_decisions()
+            .into_iter()
+            .map(decision_token)
+            .join(" "),
+    };
+    let kind = match kind {
+        DecisionKind::Direction => "direction",
+        DecisionKind::Treasure => "treasure",
+    };
+    format!("{}choose {kind} {choices}\n", state(deep_sea))
+}
+
+/// Ends a game, sent to every seat in seat order.
+pub fn scores(scores: &[u32]) -> String {
+    format!("scores {}\n", scores.iter().join(" "))
+}
+
+fn tile_token(tile: Tile) -> &'static str {
+    match tile {
+        Tile::Empty => "-",
+        Tile::Treasure(treasure) => treasure_token(treasure),
+    }
+}
+
+fn treasure_token(treasure: Treasure) -> &'static str {
+    match treasure {
+        Treasure::One => "1",
+        Treasure::Two => "2",
+        Treasure::Three => "3",
+        Treasure::Four => "4",
+    }
+}
+
+pub fn direction_token(direction: DiveDirection) -> &'static str {
+    match direction {
+        DiveDirection::Down => "down",
+        DiveDirection::Up => "up",
+    }
+}
+
+pub fn decision_token(decision: TreasureDecision) -> String {
+    match decision {
+        TreasureDecision::Ignore => "ignore".to_string(),
+        TreasureDecision::Take => "take".to_string(),
+        TreasureDecision::Return(treasure) => format!("return:{}", treasure_token(treasure)),
+    }
+}
+
+pub fn parse_direction(reply: &str) -> Option<DiveDirection> {
+    match reply.trim() {
+        "down" => Some(DiveDirection::Down),
+        "up" => Some(DiveDirection::Up),
+        _ => None,
+    }
+}
+
+pub fn parse_decision(reply: &str) -> Option<TreasureDecision> {
+    match reply.trim() {
+        "ignore" => Some(TreasureDecision::Ignore),
+        "take" => Some(TreasureDecision::Take),
+        reply => {
+            let treasure = match reply.strip_prefix("return:")? {
+                "1" => Treasure::One,
+                "2" => Treasure::Two,
+                "3" => Treasure::Three,
+                "4" => Treasure::Four,
+                _ => return None,
+            };
+            Some(TreasureDecision::Return(treasure))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        deep_sea::{DeepSea, DiveDirection, Tile},
+        protocol::{
+            DecisionKind, decision_token, direction_token, parse_decision, parse_direction,
+            parse_ready, request,
+        },
+        solver::TreasureDecision,
+        treasure::Treasure,
+    };
+
+    #[test]
+    fn test_request() {
+        let path = vec![
+            Tile::Treasure(Treasure::One),
+            Tile::Empty,
+            Tile::Treasure(Treasure::Four),
+        ];
+        let mut deep_sea = DeepSea::new(path, 2);
+        deep_sea.take_oxygen();
+        deep_sea.move_player(DiveDirection::Down, 1).unwrap();
+        deep_sea.take_treasure(TreasureDecision::Take).unwrap();
+
+        assert_eq!(
+            request(&deep_sea, DecisionKind::Treasure),
+            "state 2 0 25\n\
+             path - - 4\n\
+             player 0 down 1\n\
+             player waiting down\n\
+             choose treasure ignore return:1\n"
+        );
+        deep_sea.next_player();
+        assert_eq!(
+            request(&deep_sea, DecisionKind::Direction).lines().last(),
+            Some("choose direction down")
+        );
+    }
+
+    #[test]
+    fn test_tokens_round_trip() {
+        for direction in [DiveDirection::Down, DiveDirection::Up] {
+            assert_eq!(parse_direction(direction_token(direction)), Some(direction));
+        }
+        for decision in [
+            TreasureDecision::Ignore,
+            TreasureDecision::Take,
+            TreasureDecision::Return(Treasure::One),
+            TreasureDecision::Return(Treasure::Four),
+        ] {
+            assert_eq!(parse_decision(&decision_token(decision)), Some(decision));
+        }
+        assert_eq!(parse_direction("sideways"), None);
+        assert_eq!(parse_decision("return:5"), None);
+        assert_eq!(parse_ready("ready bot\n"), Some("bot"));
+        assert_eq!(parse_ready("ready"), Some(""));
+        assert_eq!(parse_ready("readyish"), None);
+    }
+}
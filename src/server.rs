@@ -0,0 +1,224 @@
+use std::{
+    io::{BufReader, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use crate::{
+    deep_sea::Tile,
+    engine::{Engine, FaultPolicy, RoundResult},
+    error::DeepSeaResult,
+    protocol,
+    solver::DeepSeaSolver,
+    text_solver::{DEFAULT_TIMEOUT, TextSolver},
+};
+
+/// A seat taken by a client of `GameServer`.
+pub type RemoteSolver = TextSolver<TcpStream>;
+
+/// Hosts games between clients connecting over TCP, each taking the next free seat. Clients
+/// speak the text protocol in `protocol`: besides the requests for their own decisions, they
+/// are sent the state after every turn and the scores once the game is over. People can take a
+/// seat from a terminal with e.g. `nc localhost <port>`.
+pub struct GameServer {
+    listener: TcpListener,
+    /// Without a time limit, the server waits on each client's decisions for as long as it
+    /// takes. Handshakes are always bounded by `DEFAULT_TIMEOUT`.
+    time_limit: Option<Duration>,
+    fault_policy: FaultPolicy,
+}
+
+impl GameServer {
+    pub fn bind(addr: impl ToSocketAddrs) -> DeepSeaResult<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(addr)?,
+            time_limit: None,
+            fault_policy: FaultPolicy::default(),
+        })
+    }
+
+    pub fn with_time_limit(mut self, time_limit: Duration) -> Self {
+        self.time_limit = Some(time_limit);
+        self
+    }
+
+    pub fn with_fault_policy(mut self, policy: FaultPolicy) -> Self {
+        self.fault_policy = policy;
+        self
+    }
+
+    pub fn local_addr(&self) -> DeepSeaResult<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Takes `stream` through the handshake, waiting at most `DEFAULT_TIMEOUT` for it. Also
+    /// returns a handle to the connection for sending updates while the seat is in play. A
+    /// client that fails the handshake is sent `QUIT` and disconnected, which also ends the
+    /// thread reading its replies.
+    fn seat(&self, stream: TcpStream) -> DeepSeaResult<(RemoteSolver, TcpStream)> {
+        stream.set_nodelay(true)?;
+        let reader = BufReader::new(stream.try_clone()?);
+        let updates = stream.try_clone()?;
+        match TextSolver::connect(stream, reader, DEFAULT_TIMEOUT) {
+            Ok(mut solver) => {
+                solver.set_timeout(self.time_limit.unwrap_or(Duration::MAX));
+                Ok((solver, updates))
+            }
+            Err(error) => {
+                hang_up(vec![updates], "");
+                Err(error)
+            }
+        }
+    }
+
+    /// Seats the next `num_seats` clients in the order they connect and plays a game between
+    /// them on `path`, driving it turn by turn like `Engine::play_round`. A client that fails
+    /// the handshake is turned away and its seat goes to the next one.
+    pub fn host_round(&self, path: Vec<Tile>, num_seats: usize) -> DeepSeaResult<RoundResult> {
+        let mut players: Vec<Box<dyn DeepSeaSolver>> = vec![];
+        let mut connections = vec![];
+        while players.len() < num_seats {
+            let stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(error) => {
+                    hang_up(connections, "");
+                    return Err(error.into());
+                }
+            };
+            if let Ok((solver, updates)) = self.seat(stream) {
+                players.push(Box::new(solver));
+                connections.push(updates);
+            }
+        }
+
+        let mut engine = Engine::new(path, players).with_fault_policy(self.fault_policy);
+        if let Some(time_limit) = self.time_limit {
+            engine = engine.with_time_limit(time_limit);
+        }
+        // A client that hung up is reported as a fault when it is next asked for a decision.
+        let result = engine.play_round_observed(|state| {
+            let update = protocol::state(state);
+            for connection in &mut connections {
+                let _ = connection.write_all(update.as_bytes());
+            }
+        });
+
+        let scores = result
+            .as_ref()
+            .map(|result| protocol::scores(&result.scores))
+            .unwrap_or_default();
+        hang_up(connections, &scores);
+        result
+    }
+}
+
+/// Sends `message` and `QUIT` to every seat and closes the connections.
+fn hang_up(connections: Vec<TcpStream>, message: &str) {
+    for mut connection in connections {
+        let _ = connection.write_all(format!("{message}{}", protocol::QUIT).as_bytes());
+        let _ = connection.shutdown(Shutdown::Both);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::{SocketAddr, TcpStream},
+        thread::{self, JoinHandle},
+        time::Duration,
+    };
+
+    use crate::{
+        engine::{Engine, FaultPolicy},
+        protocol,
+        server::GameServer,
+    };
+
+    /// Plays the first legal choice, returning every line the server sent.
+    fn first_choice_client(addr: SocketAddr, name: &'static str) -> JoinHandle<Vec<String>> {
+        thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.set_nodelay(true).unwrap();
+            let mut received = vec![];
+            for line in BufReader::new(stream.try_clone().unwrap()).lines() {
+                let line = line.unwrap();
+                let tokens: Vec<_> = line.split(' ').collect();
+                match tokens[0] {
+                    "deep-sea" => writeln!(stream, "ready {name}").unwrap(),
+                    "choose" => writeln!(stream, "{}", tokens[2]).unwrap(),
+                    _ => {}
+                }
+                let quit = line == "quit";
+                received.push(line);
+                if quit {
+                    break;
+                }
+            }
+            received
+        })
+    }
+
+    #[test]
+    fn test_host_round() {
+        let server = GameServer::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let clients: Vec<_> = ["a", "b"]
+            .into_iter()
+            .map(|name| first_choice_client(addr, name))
+            .collect();
+
+        let result = server.host_round(Engine::default_path(), 2).unwrap();
+        assert_eq!(result.faults, [0, 0]);
+        let scores = format!("scores {} {}", result.scores[0], result.scores[1]);
+        for client in clients {
+            let received = client.join().unwrap();
+            assert_eq!(
+                received[received.len() - 2..],
+                [scores.clone(), "quit".into()]
+            );
+            assert!(received.iter().any(|line| line.starts_with("choose")));
+        }
+    }
+
+    #[test]
+    fn test_client_hangs_up() {
+        let server = GameServer::bind("127.0.0.1:0")
+            .unwrap()
+            .with_fault_policy(FaultPolicy::Forfeit)
+            .with_time_limit(Duration::from_secs(5));
+        let addr = server.local_addr().unwrap();
+        // Connections queue up in order, so the client that hangs up takes the first seat.
+        let mut quitter = TcpStream::connect(addr).unwrap();
+        writeln!(quitter, "ready").unwrap();
+        drop(quitter);
+        let client = first_choice_client(addr, "stays");
+
+        let result = server.host_round(Engine::default_path(), 2).unwrap();
+        client.join().unwrap();
+        assert_eq!(result.faults, [1, 0]);
+        assert_eq!(result.scores[0], 0);
+    }
+
+    #[test]
+    fn test_failed_handshake_gives_up_the_seat() {
+        let server = GameServer::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut stranger = TcpStream::connect(addr).unwrap();
+        writeln!(stranger, "hello").unwrap();
+        let clients: Vec<_> = ["a", "b"]
+            .into_iter()
+            .map(|name| first_choice_client(addr, name))
+            .collect();
+
+        let result = server.host_round(Engine::default_path(), 2).unwrap();
+        assert_eq!(result.faults, [0, 0]);
+        for client in clients {
+            assert_eq!(client.join().unwrap().last().unwrap(), "quit");
+        }
+        // Turned away rather than left hanging.
+        let mut received = String::new();
+        stranger.read_to_string(&mut received).unwrap();
+        assert_eq!(received, format!("{}{}", protocol::hello(), protocol::QUIT));
+    }
+}
@@ -1,9 +1,12 @@
+use std::time::Duration;
+
 use itertools::Itertools;
 use rand::seq::SliceRandom;
 use strum_macros::EnumCount;
 
 use crate::{
     deep_sea::{DeepSea, DiveDirection},
+    error::DeepSeaError,
     treasure::Treasure,
 };
 
@@ -18,6 +21,16 @@ pub trait DeepSeaSolver {
     fn choose_direction(&mut self, deep_sea: &DeepSea, player_idx: usize) -> DiveDirection;
 
     fn take_treasure(&mut self, deep_sea: &DeepSea, player_idx: usize) -> TreasureDecision;
+
+    /// Called before each decision when the engine enforces a time limit, with the time the
+    /// decision may take. Decisions that take longer are treated as faults.
+    fn set_time_budget(&mut self, _budget: Duration) {}
+
+    /// Called after each decision. A solver that could not come to a decision of its own, such as
+    /// one whose bot process stopped answering, returns why, and the decision counts as a fault.
+    fn take_error(&mut self) -> Option<DeepSeaError> {
+        None
+    }
 }
 
 pub trait IntoSolvers {
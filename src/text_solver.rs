@@ -0,0 +1,190 @@
+use std::{
+    io::{self, BufRead, Write},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    deep_sea::{DeepSea, DiveDirection},
+    error::{DeepSeaError, DeepSeaResult},
+    protocol::{self, DecisionKind},
+    solver::{DeepSeaSolver, TreasureDecision},
+};
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A solver on the other end of a connection speaking the text protocol in `protocol`, such as a
+/// bot process or a client of `GameServer`.
+///
+/// Decisions the bot fails to make, by replying too late, replying with something other than a
+/// choice or hanging up, are reported through `take_error` and replaced with the first legal
+/// choice.
+pub struct TextSolver<W: Write> {
+    /// `None` once the session is closed.
+    writer: Option<W>,
+    replies: Receiver<String>,
+    name: String,
+    /// How long to wait for a reply when the engine sets no time budget.
+    timeout: Duration,
+    budget: Option<Duration>,
+    /// Replies still owed to requests that timed out, which are skipped when they arrive.
+    late_replies: usize,
+    error: Option<DeepSeaError>,
+}
+
+impl<W: Write> TextSolver<W> {
+    /// Opens a session over `writer` and `reader`, waiting up to `timeout` for the handshake and
+    /// for each decision the engine sets no time budget for.
+    pub fn connect(
+        writer: W,
+        reader: impl BufRead + Send + 'static,
+        timeout: Duration,
+    ) -> DeepSeaResult<Self> {
+        let (sender, replies) = mpsc::channel();
+        thread::spawn(move || {
+            for line in reader.lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut solver = Self {
+            writer: Some(writer),
+            replies,
+            name: String::new(),
+            timeout,
+            budget: None,
+            late_replies: 0,
+            error: None,
+        };
+        let reply = solver.exchange(&protocol::hello(), None, timeout)?;
+        solver.name = protocol::parse_ready(&reply)
+            .ok_or_else(|| DeepSeaError::Protocol {
+                player_idx: None,
+                message: format!("Expected a ready line, got {reply:?}"),
+            })?
+            .to_string();
+        Ok(solver)
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// The name the bot gave in the handshake, if any.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Sends lines that need no reply, such as state updates.
+    pub fn send(&mut self, message: &str) -> io::Result<()> {
+        let writer = self.writer.as_mut().ok_or(io::ErrorKind::BrokenPipe)?;
+        writer.write_all(message.as_bytes())?;
+        writer.flush()
+    }
+
+    /// Sends `QUIT` and closes the writer, after which decisions fail. Dropping the solver only
+    /// closes the writer, leaving the rest of the session to whoever shares the connection.
+    pub fn close(&mut self) {
+        let _ = self.send(protocol::QUIT);
+        self.writer = None;
+    }
+
+    /// Sends `message` and waits up to `limit` for the line answering it.
+    fn exchange(
+        &mut self,
+        message: &str,
+        player_idx: Option<usize>,
+        limit: Duration,
+    ) -> DeepSeaResult<String> {
+        let start = Instant::now();
+        self.send(message).map_err(|err| DeepSeaError::Protocol {
+            player_idx,
+            message: format!("Could not write to the bot: {err}"),
+        })?;
+        loop {
+            match self
+                .replies
+                .recv_timeout(limit.saturating_sub(start.elapsed()))
+            {
+                Ok(_) if self.late_replies > 0 => self.late_replies -= 1,
+                Ok(reply) => return Ok(reply),
+                Err(RecvTimeoutError::Timeout) => {
+                    self.late_replies += 1;
+                    let elapsed = start.elapsed();
+                    return Err(match player_idx {
+                        Some(player_idx) => DeepSeaError::Timeout {
+                            player_idx,
+                            elapsed,
+                            limit,
+                        },
+                        None => DeepSeaError::Protocol {
+                            player_idx,
+                            message: format!("No reply within {elapsed:?}"),
+                        },
+                    });
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(DeepSeaError::Protocol {
+                        player_idx,
+                        message: "The bot hung up".to_string(),
+                    });
+                }
+            }
+        }
+    }
+
+    fn decide<T>(
+        &mut self,
+        deep_sea: &DeepSea,
+        player_idx: usize,
+        kind: DecisionKind,
+        parse: impl FnOnce(&str) -> Option<T>,
+    ) -> DeepSeaResult<T> {
+        let limit = self.budget.take().unwrap_or(self.timeout);
+        let reply = self.exchange(&protocol::request(deep_sea, kind), Some(player_idx), limit)?;
+        parse(&reply).ok_or_else(|| DeepSeaError::Protocol {
+            player_idx: Some(player_idx),
+            message: format!("Unrecognized {kind:?} reply {reply:?}"),
+        })
+    }
+}
+
+impl<W: Write> DeepSeaSolver for TextSolver<W> {
+    fn choose_direction(&mut self, deep_sea: &DeepSea, player_idx: usize) -> DiveDirection {
+        self.decide(
+            deep_sea,
+            player_idx,
+            DecisionKind::Direction,
+            protocol::parse_direction,
+        )
+        .unwrap_or_else(|error| {
+            self.error = Some(error);
+            deep_sea.legal_directions()[0]
+        })
+    }
+
+    fn take_treasure(&mut self, deep_sea: &DeepSea, player_idx: usize) -> TreasureDecision {
+        self.decide(
+            deep_sea,
+            player_idx,
+            DecisionKind::Treasure,
+            protocol::parse_decision,
+        )
+        .unwrap_or_else(|error| {
+            self.error = Some(error);
+            TreasureDecision::Ignore
+        })
+    }
+
+    fn set_time_budget(&mut self, budget: Duration) {
+        self.budget = Some(budget);
+    }
+
+    fn take_error(&mut self) -> Option<DeepSeaError> {
+        self.error.take()
+    }
+}
@@ -1,7 +1,7 @@
 use std::fmt::Display;
 use strum_macros::EnumCount;
 
-use rand::Rng;
+use crate::chance::{ChanceProvider, RngChance};
 
 #[derive(Clone, Copy, Debug, EnumCount, PartialEq, Eq, Hash)]
 pub enum Treasure {
@@ -50,21 +50,25 @@ impl TreasureValueAssigner {
     }
 
     pub fn assign_value(&mut self, treasure: Treasure) -> u32 {
+        self.assign_value_with(treasure, &mut RngChance::new(rand::rng()))
+    }
+
+    /// Reveals a chip of `treasure` with a value drawn from `chance`.
+    pub fn assign_value_with(
+        &mut self,
+        treasure: Treasure,
+        chance: &mut impl ChanceProvider,
+    ) -> u32 {
         let idx = treasure.idx();
         let bucket = &mut self.buckets[idx];
         debug_assert!(bucket.iter().any(|&count| count > 0));
 
-        let total: u32 = bucket.iter().sum();
-        let mut choice = rand::rng().random_range(1..=total);
-        let (value_idx, count) = bucket
-            .iter_mut()
-            .enumerate()
-            .find(|&(_, &mut count)| {
-                choice = choice.saturating_sub(count);
-                choice == 0
-            })
-            .unwrap();
-        *count -= 1;
+        let value_idx = chance.chip_value(treasure, bucket);
+        assert!(
+            bucket.get(value_idx).is_some_and(|&count| count > 0),
+            "No {treasure:?} chip with value offset {value_idx} is left: {bucket:?}"
+        );
+        bucket[value_idx] -= 1;
 
         treasure.base_value() + value_idx as u32
     }